@@ -1,17 +1,24 @@
-#[cfg(feature = "async")]
+#[cfg(any(feature = "async", all(feature = "subscriptions", feature = "sync")))]
 use std::{future::Future, pin::Pin};
 
 use std::sync::{Arc, Mutex};
 
-#[cfg(any(feature = "async", feature = "sync"))]
+#[cfg(any(feature = "async", feature = "sync", feature = "subscriptions"))]
 use std::time::Duration;
 
-#[cfg(feature = "subscriptions")]
-use crate::socket::Socket;
 use crate::{
     rate_limiter::RateLimiter,
     request::{Client, Headers},
 };
+#[cfg(feature = "subscriptions")]
+use crate::{
+    reconnect::ReconnectPolicy,
+    socket::{Socket, SocketPool},
+    subscription::QueueLimit,
+};
+
+#[cfg(all(feature = "subscriptions", feature = "sync"))]
+type SpawnFn = fn(Pin<Box<dyn Future<Output = ()> + Send>>) -> Result<(), String>;
 
 #[derive(Debug)]
 pub struct Config {
@@ -27,7 +34,29 @@ pub struct Config {
     pub subscription_auth_url: String,
     pub rate_limiter: Arc<Mutex<RateLimiter>>,
     #[cfg(feature = "subscriptions")]
-    pub socket: Box<dyn Socket>,
+    pub socket: Arc<dyn Socket>,
+    // subscriptions are spread over the pool's connections instead of
+    // `socket` if it's set
+    #[cfg(feature = "subscriptions")]
+    pub socket_pool: Option<SocketPool>,
+    #[cfg(feature = "subscriptions")]
+    pub reconnect: ReconnectPolicy,
+    // how long to wait for the server to accept a subscribe
+    #[cfg(feature = "subscriptions")]
+    pub subscribe_timeout: Duration,
+    // channels older than this are requested again before subscribing to
+    // them, and subscriptions move to a new one shortly before theirs is this
+    // old, `None` only does so once authorizing them fails
+    #[cfg(feature = "subscriptions")]
+    pub channel_lifetime: Option<Duration>,
+    // query for records created while the socket was reconnecting, records
+    // updated or deleted in the meantime can't be found so update and delete
+    // subscriptions only get a `ConnectionEvent::Error` saying so
+    #[cfg(all(feature = "subscriptions", feature = "async"))]
+    pub backfill: bool,
+    // the default for new subscriptions, `None` is unbounded
+    #[cfg(feature = "subscriptions")]
+    pub queue_limit: Option<QueueLimit>,
     pub client: Box<dyn Client>,
     pub headers: Headers,
     pub now: fn() -> u64,
@@ -35,6 +64,10 @@ pub struct Config {
     pub sleep: fn(Duration) -> Pin<Box<dyn Future<Output = ()> + Send + Sync>>,
     #[cfg(feature = "sync")]
     pub sleep_sync: fn(Duration) -> (),
+    // runs subscriptions made from sync code on a tokio runtime that isn't the
+    // caller's, pnwkit-rs starts a hidden one on a thread of its own
+    #[cfg(all(feature = "subscriptions", feature = "sync"))]
+    pub spawn: SpawnFn,
     pub user_agent: String,
 }
 
@@ -101,7 +134,43 @@ impl Config {
 
     #[cfg(feature = "subscriptions")]
     pub fn set_socket(mut self, socket: Box<dyn Socket>) -> Self {
-        self.socket = socket;
+        self.socket = Arc::from(socket);
+        self
+    }
+
+    #[cfg(feature = "subscriptions")]
+    pub fn set_socket_pool(mut self, socket_pool: Option<SocketPool>) -> Self {
+        self.socket_pool = socket_pool;
+        self
+    }
+
+    #[cfg(feature = "subscriptions")]
+    pub fn set_reconnect(mut self, reconnect: ReconnectPolicy) -> Self {
+        self.reconnect = reconnect;
+        self
+    }
+
+    #[cfg(feature = "subscriptions")]
+    pub fn set_subscribe_timeout(mut self, subscribe_timeout: Duration) -> Self {
+        self.subscribe_timeout = subscribe_timeout;
+        self
+    }
+
+    #[cfg(feature = "subscriptions")]
+    pub fn set_channel_lifetime(mut self, channel_lifetime: Option<Duration>) -> Self {
+        self.channel_lifetime = channel_lifetime;
+        self
+    }
+
+    #[cfg(all(feature = "subscriptions", feature = "async"))]
+    pub fn set_backfill(mut self, backfill: bool) -> Self {
+        self.backfill = backfill;
+        self
+    }
+
+    #[cfg(feature = "subscriptions")]
+    pub fn set_queue_limit(mut self, queue_limit: Option<QueueLimit>) -> Self {
+        self.queue_limit = queue_limit;
         self
     }
 
@@ -130,6 +199,12 @@ impl Config {
         self
     }
 
+    #[cfg(all(feature = "subscriptions", feature = "sync"))]
+    pub fn set_spawn(mut self, spawn: SpawnFn) -> Self {
+        self.spawn = spawn;
+        self
+    }
+
     pub fn set_user_agent(mut self, user_agent: String) -> Self {
         self.user_agent = user_agent;
         self
@@ -1,16 +1,18 @@
 use crate::{data::Object, Value, Variable};
 use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub enum FieldType {
     Node(Field),
     Leaf(String),
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Field {
     pub(crate) name: String,
     pub(crate) alias: Option<String>,
+    #[serde(with = "arguments")]
     pub(crate) arguments: Object,
     pub(crate) fields: Vec<FieldType>,
     pub(crate) paginate: bool,
@@ -75,8 +77,22 @@ impl Field {
         vars
     }
 
-    pub(crate) fn tree_will_paginate(&self) -> bool {
+    pub(crate) fn set_paginate_argument(&self, name: String, value: Value) {
         if self.paginate {
+            self.arguments.insert(name.clone(), value.clone());
+        }
+        for field in &self.fields {
+            match field {
+                FieldType::Node(field) => field.set_paginate_argument(name.clone(), value.clone()),
+                FieldType::Leaf(_) => {},
+            }
+        }
+    }
+
+    // fields with an explicit page argument don't need the shared `__page`
+    // variable, such as the ones driven by a `MultiPaginator`
+    pub(crate) fn tree_will_paginate(&self) -> bool {
+        if self.paginate && !self.arguments.contains_key("page") {
             return true;
         }
         for field in &self.fields {
@@ -122,3 +138,83 @@ pub fn field_as(name: &str, alias: &str) -> Field {
         .set_name(name.into())
         .set_alias(alias.into())
 }
+
+// `Value::Variable` can't round trip through the serde impls on `Value`, so
+// arguments are (de)serialized through a tagged mirror of it instead
+mod arguments {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    use crate::{data::Object, Value, Variable, VariableType};
+
+    #[derive(Deserialize, Serialize)]
+    enum Argument {
+        None,
+        Bool(bool),
+        Int(i64),
+        Float(f64),
+        String(String),
+        Variable(String, VariableType),
+        Object(Vec<(String, Argument)>),
+        Array(Vec<Argument>),
+    }
+
+    impl From<&Value> for Argument {
+        fn from(value: &Value) -> Self {
+            match value {
+                Value::None => Self::None,
+                Value::Bool(v) => Self::Bool(*v),
+                Value::Int(v) => Self::Int(*v),
+                Value::Float(v) => Self::Float(*v),
+                Value::String(v) => Self::String(v.clone()),
+                Value::Variable(v) => Self::Variable(v.name.clone(), v.variable_type.clone()),
+                Value::Object(v) => Self::Object(
+                    v.iter()
+                        .map(|i| (i.key().clone(), i.value().into()))
+                        .collect(),
+                ),
+                Value::Array(v) => Self::Array(v.iter().map(|i| i.into()).collect()),
+            }
+        }
+    }
+
+    impl From<Argument> for Value {
+        fn from(argument: Argument) -> Self {
+            match argument {
+                Argument::None => Self::None,
+                Argument::Bool(v) => Self::Bool(v),
+                Argument::Int(v) => Self::Int(v),
+                Argument::Float(v) => Self::Float(v),
+                Argument::String(v) => Self::String(v),
+                Argument::Variable(name, variable_type) => Self::Variable(Variable {
+                    name,
+                    variable_type,
+                }),
+                Argument::Object(v) => {
+                    Self::Object(v.into_iter().map(|(k, v)| (k, v.into())).collect())
+                },
+                Argument::Array(v) => Self::Array(v.into_iter().map(|i| i.into()).collect()),
+            }
+        }
+    }
+
+    pub(super) fn serialize<S>(arguments: &Object, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_seq(
+            arguments
+                .iter()
+                .map(|i| (i.key().clone(), Argument::from(i.value()))),
+        )
+    }
+
+    pub(super) fn deserialize<'de, D>(deserializer: D) -> Result<Object, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Ok(Vec::<(String, Argument)>::deserialize(deserializer)?
+            .into_iter()
+            .map(|(k, v)| (k, v.into()))
+            .collect())
+    }
+}
@@ -0,0 +1,373 @@
+use std::{
+    collections::BTreeMap,
+    fs::{self, File, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Object, SubscriptionMessage};
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Entry {
+    Message {
+        id: u64,
+        model: String,
+        event: String,
+        bulk: bool,
+        channel: String,
+        received: u64,
+        backfill: bool,
+        data: Object,
+    },
+    Ack {
+        id: u64,
+    },
+}
+
+impl Entry {
+    fn message(id: u64, message: &SubscriptionMessage) -> Self {
+        Self::Message {
+            id,
+            model: message.model.to_string(),
+            event: message.event.clone(),
+            bulk: message.bulk,
+            channel: message.channel.clone(),
+            received: message.received,
+            backfill: message.backfill,
+            data: message.data.clone(),
+        }
+    }
+}
+
+struct JournalFile {
+    file: File,
+    next_id: u64,
+    unacked: BTreeMap<u64, SubscriptionMessage>,
+    // entries in the file that compacting would drop
+    stale: usize,
+}
+
+// an append-only log of a subscription's messages, messages stay in it until
+// they're acknowledged and the ones that weren't are delivered again when the
+// journal is opened, so every message is handled at least once
+//
+// each subscription needs a journal of its own, writes are flushed to the os
+// so they survive the process crashing but not the machine
+pub struct SubscriptionJournal {
+    path: PathBuf,
+    file: Mutex<JournalFile>,
+    // compacts once this many entries are stale, `None` leaves it to `compact`
+    compact_after: Option<usize>,
+}
+
+impl SubscriptionJournal {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, String> {
+        let path = path.as_ref().to_path_buf();
+        let mut next_id = 1;
+        let mut unacked = BTreeMap::new();
+        let mut stale = 0;
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e.to_string()),
+        };
+        for line in contents.lines() {
+            // the last line is cut short if a write was interrupted, that
+            // message was never delivered so it's fine to lose
+            let entry = match serde_json::from_str::<Entry>(line) {
+                Ok(entry) => entry,
+                Err(_) => {
+                    stale += 1;
+                    continue;
+                },
+            };
+            match entry {
+                Entry::Message {
+                    id,
+                    model,
+                    event,
+                    bulk,
+                    channel,
+                    received,
+                    backfill,
+                    data,
+                } => {
+                    next_id = next_id.max(id + 1);
+                    // written by a version that knows models this one doesn't,
+                    // it can't be delivered so it goes with the next compaction
+                    let model = match model.parse() {
+                        Ok(model) => model,
+                        Err(_) => {
+                            stale += 1;
+                            continue;
+                        },
+                    };
+                    unacked.insert(
+                        id,
+                        SubscriptionMessage {
+                            model,
+                            event,
+                            bulk,
+                            channel,
+                            received,
+                            backfill,
+                            journal_id: Some(id),
+                            data,
+                        },
+                    );
+                },
+                Entry::Ack { id } => {
+                    if unacked.remove(&id).is_some() {
+                        stale += 1;
+                    }
+                    stale += 1;
+                },
+            }
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .map_err(|e| e.to_string())?;
+        // otherwise the next entry would continue the cut short line
+        if !contents.is_empty() && !contents.ends_with('\n') {
+            write_line(&mut file, Vec::new())?;
+        }
+        Ok(Self {
+            path,
+            file: Mutex::new(JournalFile {
+                file,
+                next_id,
+                unacked,
+                stale,
+            }),
+            compact_after: Some(1024),
+        })
+    }
+
+    pub fn set_compact_after(mut self, compact_after: Option<usize>) -> Self {
+        self.compact_after = compact_after;
+        self
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // the number of messages that weren't acknowledged
+    pub fn len(&self) -> usize {
+        self.file.lock().unwrap().unacked.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // in the order they were appended
+    pub fn unacked(&self) -> Vec<SubscriptionMessage> {
+        self.file
+            .lock()
+            .unwrap()
+            .unacked
+            .values()
+            .cloned()
+            .collect()
+    }
+
+    // writes the message and sets its `journal_id`
+    pub fn append(&self, message: &mut SubscriptionMessage) -> Result<(), String> {
+        let mut file = self.file.lock().unwrap();
+        let id = file.next_id;
+        write_entry(&mut file.file, &Entry::message(id, message))?;
+        file.next_id += 1;
+        message.journal_id = Some(id);
+        file.unacked.insert(id, message.clone());
+        Ok(())
+    }
+
+    // acknowledging a message again or one that isn't in the journal does
+    // nothing
+    pub fn ack(&self, id: u64) -> Result<(), String> {
+        let mut file = self.file.lock().unwrap();
+        if file.unacked.remove(&id).is_none() {
+            return Ok(());
+        }
+        write_entry(&mut file.file, &Entry::Ack { id })?;
+        file.stale += 2;
+        match self.compact_after {
+            Some(compact_after) if file.stale >= compact_after => self.compact_file(&mut file),
+            _ => Ok(()),
+        }
+    }
+
+    // rewrites the journal with only the messages that weren't acknowledged
+    pub fn compact(&self) -> Result<(), String> {
+        let mut file = self.file.lock().unwrap();
+        self.compact_file(&mut file)
+    }
+
+    fn compact_file(&self, file: &mut JournalFile) -> Result<(), String> {
+        // written next to the journal and renamed over it, so a crash leaves
+        // either the old or the new one
+        let mut path = self.path.clone().into_os_string();
+        path.push(".compact");
+        let path = PathBuf::from(path);
+        let mut compacted = File::create(&path).map_err(|e| e.to_string())?;
+        for (id, message) in &file.unacked {
+            write_entry(&mut compacted, &Entry::message(*id, message))?;
+        }
+        compacted.sync_all().map_err(|e| e.to_string())?;
+        fs::rename(&path, &self.path).map_err(|e| e.to_string())?;
+        file.file = OpenOptions::new()
+            .append(true)
+            .open(&self.path)
+            .map_err(|e| e.to_string())?;
+        file.stale = 0;
+        Ok(())
+    }
+}
+
+fn write_entry(file: &mut File, entry: &Entry) -> Result<(), String> {
+    write_line(file, serde_json::to_vec(entry).map_err(|e| e.to_string())?)
+}
+
+// one write per line so an interrupted one only cuts the last line
+fn write_line(file: &mut File, mut line: Vec<u8>) -> Result<(), String> {
+    line.push(b'\n');
+    file.write_all(&line).map_err(|e| e.to_string())?;
+    file.flush().map_err(|e| e.to_string())
+}
+
+impl std::fmt::Debug for SubscriptionJournal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SubscriptionJournal")
+            .field("path", &self.path)
+            .field("unacked", &self.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, io::Write, path::PathBuf};
+
+    use super::SubscriptionJournal;
+    use crate::{Object, SubscriptionMessage, SubscriptionModel, Value};
+
+    // a journal file of its own for each test, removed first in case an
+    // earlier run left it behind
+    fn path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "pnwkit-journal-{}-{}.jsonl",
+            std::process::id(),
+            name
+        ));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    fn message(id: i64) -> SubscriptionMessage {
+        let data = Object::new();
+        data.insert("id".into(), Value::Int(id));
+        SubscriptionMessage {
+            model: SubscriptionModel::Nation,
+            event: "NATION_UPDATE".into(),
+            bulk: false,
+            channel: "channel".into(),
+            received: 0,
+            backfill: false,
+            journal_id: None,
+            data,
+        }
+    }
+
+    fn unacked(journal: &SubscriptionJournal) -> Vec<(u64, Option<i64>)> {
+        journal
+            .unacked()
+            .iter()
+            .map(|m| (m.journal_id.unwrap(), m.data.get("id").unwrap().as_i64()))
+            .collect()
+    }
+
+    fn lines(path: &PathBuf) -> usize {
+        fs::read_to_string(path).unwrap().lines().count()
+    }
+
+    #[test]
+    fn unacknowledged_messages_survive_a_crash() {
+        let path = path("crash");
+        let journal = SubscriptionJournal::open(&path).unwrap();
+        for id in [10, 20, 30] {
+            let mut message = message(id);
+            journal.append(&mut message).unwrap();
+            assert!(message.journal_id.is_some());
+        }
+        journal.ack(1).unwrap();
+        drop(journal);
+        // the write that was going on when the process died
+        let mut file = fs::OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"{\"type\":\"message\",\"id\":4,").unwrap();
+        drop(file);
+
+        let journal = SubscriptionJournal::open(&path).unwrap();
+        assert_eq!(unacked(&journal), vec![(2, Some(20)), (3, Some(30))]);
+        journal.ack(2).unwrap();
+        // acknowledging twice or something unknown does nothing
+        journal.ack(2).unwrap();
+        journal.ack(99).unwrap();
+        journal.compact().unwrap();
+        assert_eq!(lines(&path), 1);
+
+        let journal = SubscriptionJournal::open(&path).unwrap();
+        assert_eq!(unacked(&journal), vec![(3, Some(30))]);
+        let mut message = message(40);
+        journal.append(&mut message).unwrap();
+        assert_eq!(message.journal_id, Some(4));
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn acknowledging_compacts_the_file() {
+        let path = path("compact");
+        let journal = SubscriptionJournal::open(&path)
+            .unwrap()
+            .set_compact_after(Some(4));
+        for id in [1, 2, 3] {
+            journal.append(&mut message(id)).unwrap();
+        }
+        journal.ack(1).unwrap();
+        assert_eq!(lines(&path), 4);
+        journal.ack(2).unwrap();
+        assert_eq!(lines(&path), 1);
+        // appending goes on in the compacted file
+        journal.append(&mut message(4)).unwrap();
+        let journal = SubscriptionJournal::open(&path).unwrap();
+        assert_eq!(unacked(&journal), vec![(3, Some(3)), (4, Some(4))]);
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn unknown_models_are_skipped() {
+        let path = path("unknown");
+        let journal = SubscriptionJournal::open(&path).unwrap();
+        journal.append(&mut message(1)).unwrap();
+        drop(journal);
+        let contents = fs::read_to_string(&path).unwrap();
+        let unknown = contents
+            .replace("\"nation\"", "\"spaceship\"")
+            .replace("\"id\":1,", "\"id\":2,");
+        fs::write(&path, unknown + &contents).unwrap();
+
+        let journal = SubscriptionJournal::open(&path).unwrap();
+        assert_eq!(unacked(&journal), vec![(1, Some(1))]);
+        // its id isn't reused
+        let mut message = message(3);
+        journal.append(&mut message).unwrap();
+        assert_eq!(message.journal_id, Some(3));
+        let _ = fs::remove_file(&path);
+    }
+}
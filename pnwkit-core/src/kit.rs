@@ -1,25 +1,44 @@
+#[cfg(any(feature = "async", feature = "sync"))]
+use crate::MultiPaginator;
+#[cfg(feature = "async")]
+use crate::ShardedPaginator;
 use crate::{
     data::QueryReturn,
     query::{Query, QueryType},
     request::{ContentType, Method, Request, Response},
     resolve::Resolve,
     variable::Variables,
-    Config, Data, Field, Paginator,
+    Config, Data, Field, Paginator, PaginatorCheckpoint,
 };
 #[cfg(feature = "subscriptions")]
 use crate::{
     data::SubscriptionAuthData,
-    subscription::{Subscription, SubscriptionEvent, SubscriptionModel},
-    to_query_string::ToQueryString,
+    journal::SubscriptionJournal,
+    payload::SubscriptionPayload,
+    protocol::PusherProtocol,
+    socket::{ConnectionEvent, ConnectionState, Socket, SocketStatus},
+    subscription::{
+        ChannelAuth, QueueLimit, Subscription, SubscriptionEvent, SubscriptionModel,
+        SubscriptionState, TypedSubscription,
+    },
+    subscription_filter::SubscriptionFilter,
+    subscription_set::SubscriptionSet,
     Object, Value,
 };
+#[cfg(all(feature = "subscriptions", feature = "async"))]
+use crate::{field::field, payload::payload_fields};
+use serde::de::DeserializeOwned;
 use serde_json::json;
-use std::{sync::Arc, time::Duration};
+use std::sync::Arc;
+#[cfg(any(feature = "async", feature = "sync", feature = "subscriptions"))]
+use std::time::Duration;
+#[cfg(feature = "subscriptions")]
+use tokio::sync::broadcast;
 
 type GetResult = Result<Data, String>;
 
 #[cfg(feature = "subscriptions")]
-type SubscriptionResult = Result<Arc<Subscription>, String>;
+type SubscriptionResult = Result<Subscription, String>;
 
 #[derive(Clone, Debug)]
 pub struct Kit {
@@ -213,6 +232,33 @@ impl Kit {
         Paginator::new(query)
     }
 
+    pub fn paginator_as<T: DeserializeOwned + 'static>(&self, field: Field) -> Paginator<T> {
+        let query = Query::new(QueryType::Query).field(field);
+        Paginator::new(query)
+    }
+
+    #[cfg(feature = "async")]
+    pub fn sharded_paginator(
+        &self,
+        field: Field,
+        min_id: i64,
+        max_id: i64,
+        shards: u32,
+    ) -> ShardedPaginator {
+        ShardedPaginator::new(field, min_id, max_id, shards)
+    }
+
+    #[cfg(feature = "async")]
+    pub fn sharded_paginator_as<T: DeserializeOwned + 'static>(
+        &self,
+        field: Field,
+        min_id: i64,
+        max_id: i64,
+        shards: u32,
+    ) -> ShardedPaginator<T> {
+        ShardedPaginator::new(field, min_id, max_id, shards)
+    }
+
     pub fn paginator_with_capacity(&self, field: Field, capacity: u16) -> Paginator {
         let query = Query::new(QueryType::Query).field(field);
         Paginator::with_capacity(query, capacity)
@@ -233,13 +279,28 @@ impl Kit {
         Paginator::with_capacity_and_variables(query, capacity, variables)
     }
 
+    #[cfg(any(feature = "async", feature = "sync"))]
+    pub fn multi_paginator(&self) -> MultiPaginator {
+        MultiPaginator::new()
+    }
+
+    #[cfg(any(feature = "async", feature = "sync"))]
+    pub fn multi_paginator_with_variables(&self, variables: Variables) -> MultiPaginator {
+        MultiPaginator::with_variables(variables)
+    }
+
+    pub fn paginator_from_checkpoint(&self, checkpoint: PaginatorCheckpoint) -> Paginator {
+        Paginator::from_checkpoint(checkpoint)
+    }
+
     #[cfg(feature = "subscriptions")]
     pub async fn subscribe(
         &self,
         model: SubscriptionModel,
         event: SubscriptionEvent,
     ) -> SubscriptionResult {
-        self.subscribe_inner(model, event, Object::new()).await
+        self.subscribe_inner(model, event, Object::new(), self.config.queue_limit, None)
+            .await
     }
 
     #[cfg(feature = "subscriptions")]
@@ -249,7 +310,100 @@ impl Kit {
         event: SubscriptionEvent,
         filters: Object,
     ) -> SubscriptionResult {
-        self.subscribe_inner(model, event, filters).await
+        self.subscribe_inner(model, event, filters, self.config.queue_limit, None)
+            .await
+    }
+
+    #[cfg(feature = "subscriptions")]
+    pub async fn subscribe_with_queue_limit(
+        &self,
+        model: SubscriptionModel,
+        event: SubscriptionEvent,
+        filters: Object,
+        queue_limit: Option<QueueLimit>,
+    ) -> SubscriptionResult {
+        self.subscribe_inner(model, event, filters, queue_limit, None)
+            .await
+    }
+
+    // unacknowledged messages in the journal are delivered before new ones
+    #[cfg(feature = "subscriptions")]
+    pub async fn subscribe_with_journal(
+        &self,
+        model: SubscriptionModel,
+        event: SubscriptionEvent,
+        filters: Object,
+        journal: SubscriptionJournal,
+    ) -> SubscriptionResult {
+        self.subscribe_inner(
+            model,
+            event,
+            filters,
+            self.config.queue_limit,
+            Some(journal),
+        )
+        .await
+    }
+
+    #[cfg(feature = "subscriptions")]
+    pub async fn subscribe_with_filter(
+        &self,
+        event: SubscriptionEvent,
+        filter: SubscriptionFilter,
+    ) -> SubscriptionResult {
+        self.subscribe_inner(
+            filter.model().clone(),
+            event,
+            filter.to_object(),
+            self.config.queue_limit,
+            None,
+        )
+        .await
+    }
+
+    #[cfg(feature = "subscriptions")]
+    pub async fn subscribe_typed<T: SubscriptionPayload>(
+        &self,
+        event: SubscriptionEvent,
+    ) -> Result<TypedSubscription<T>, String> {
+        self.subscribe_inner(
+            T::model(),
+            event,
+            Object::new(),
+            self.config.queue_limit,
+            None,
+        )
+        .await
+        .map(TypedSubscription::new)
+    }
+
+    #[cfg(feature = "subscriptions")]
+    pub async fn subscribe_typed_with_filters<T: SubscriptionPayload>(
+        &self,
+        event: SubscriptionEvent,
+        filters: Object,
+    ) -> Result<TypedSubscription<T>, String> {
+        self.subscribe_inner(T::model(), event, filters, self.config.queue_limit, None)
+            .await
+            .map(TypedSubscription::new)
+    }
+
+    #[cfg(feature = "subscriptions")]
+    pub async fn subscribe_typed_with_filter<T: SubscriptionPayload>(
+        &self,
+        event: SubscriptionEvent,
+        filter: SubscriptionFilter,
+    ) -> Result<TypedSubscription<T>, String> {
+        if *filter.model() != T::model() {
+            return Err(format!(
+                "a {} filter can't be used for {} payloads",
+                filter.model().to_string(),
+                T::model().to_string()
+            ));
+        }
+        self.subscribe_with_filter(event, filter)
+            .await
+            .map(TypedSubscription::new)
     }
 
     #[cfg(feature = "subscriptions")]
@@ -258,74 +412,546 @@ impl Kit {
         model: SubscriptionModel,
         event: SubscriptionEvent,
         filters: Object,
+        queue_limit: Option<QueueLimit>,
+        journal: Option<SubscriptionJournal>,
     ) -> SubscriptionResult {
-        self.config.socket.init(self.clone()).await;
         let channel = self
             .request_subscription_channel(&model, &event, &filters)
             .await?;
 
-        let subscription = Subscription::new(model, event, filters, channel);
+        let subscription = Arc::new(
+            SubscriptionState::new(model, event, filters, channel)
+                .set_queue_limit(queue_limit)
+                .set_journal(journal),
+        );
+        subscription.set_channel_requested((self.config.now)());
+        subscription.replay().await;
 
-        self.subscribe_request(Arc::new(subscription)).await
+        if let Err(e) = self.subscribe_request(subscription.clone()).await {
+            if let (Some(pool), Some(socket)) = (&self.config.socket_pool, subscription.socket()) {
+                pool.release(&socket);
+            }
+            return Err(e);
+        }
+        self.spawn_reauthorization(&subscription);
+
+        Ok(Subscription::new(self.clone(), subscription))
     }
 
     #[cfg(feature = "subscriptions")]
-    pub async fn subscribe_request(&self, subscription: Arc<Subscription>) -> SubscriptionResult {
-        if !self.config.socket.get_connected().is_set().await {
-            self.config.socket.connect_ref().await?;
-            self.config.socket.start_ping_pong_task();
+    pub fn subscription_set<K: Clone + PartialEq + Unpin>(&self) -> SubscriptionSet<K> {
+        SubscriptionSet::new(self.clone())
+    }
+
+    // the socket the subscription was assigned to, the first call assigns it
+    // one from the pool or the config's socket if there's no pool
+    #[cfg(feature = "subscriptions")]
+    pub(crate) async fn subscription_socket(
+        &self,
+        subscription: &SubscriptionState,
+    ) -> Arc<dyn Socket> {
+        if let Some(socket) = subscription.socket() {
+            return socket;
         }
+        let socket = match &self.config.socket_pool {
+            Some(pool) => pool.assign(&subscription.model),
+            None => self.config.socket.clone(),
+        };
+        socket.init(self.clone()).await;
+        subscription.set_socket(socket.clone());
+        socket
+    }
+
+    // the pool's connections if there is one, the config's socket otherwise
+    #[cfg(feature = "subscriptions")]
+    pub fn sockets(&self) -> Vec<Arc<dyn Socket>> {
+        match &self.config.socket_pool {
+            Some(pool) => pool.sockets(),
+            None => vec![self.config.socket.clone()],
+        }
+    }
+
+    #[cfg(feature = "subscriptions")]
+    pub fn connection_events(&self) -> broadcast::Receiver<ConnectionEvent> {
+        match &self.config.socket_pool {
+            Some(pool) => pool.connection_events(),
+            None => self.config.socket.connection_events(),
+        }
+    }
 
+    // the least healthy state of the kit's connections
+    #[cfg(feature = "subscriptions")]
+    pub async fn connection_state(&self) -> ConnectionState {
+        let mut states = Vec::new();
+        for socket in self.sockets() {
+            states.push(socket.get_state().await);
+        }
+        ConnectionState::combine(states)
+    }
+
+    // the kit's connections combined, see `SocketStatus::combine`,
+    // subscriptions report their own with `Subscription::socket_status`
+    #[cfg(feature = "subscriptions")]
+    pub async fn socket_status(&self) -> SocketStatus {
+        let mut statuses = Vec::new();
+        for socket in self.sockets() {
+            statuses.push(socket.status().await);
+        }
+        SocketStatus::combine(statuses)
+    }
+
+    #[cfg(feature = "subscriptions")]
+    pub async fn unsubscribe(&self, subscription: &Subscription) -> Result<(), String> {
+        self.unsubscribe_state(subscription.state()).await
+    }
+
+    // the subscription runs on `Config::spawn`'s runtime, with pnwkit-rs the
+    // first sync subscription starts a thread running a tokio runtime that
+    // lives as long as the process
+    #[cfg(all(feature = "subscriptions", feature = "sync"))]
+    pub fn subscribe_sync(
+        &self,
+        model: SubscriptionModel,
+        event: SubscriptionEvent,
+    ) -> SubscriptionResult {
+        let kit = self.clone();
+        self.block_on(async move { kit.subscribe(model, event).await })?
+    }
+
+    #[cfg(all(feature = "subscriptions", feature = "sync"))]
+    pub fn subscribe_with_filter_sync(
+        &self,
+        event: SubscriptionEvent,
+        filter: SubscriptionFilter,
+    ) -> SubscriptionResult {
+        let kit = self.clone();
+        self.block_on(async move { kit.subscribe_with_filter(event, filter).await })?
+    }
+
+    #[cfg(all(feature = "subscriptions", feature = "sync"))]
+    pub fn unsubscribe_sync(&self, subscription: &Subscription) -> Result<(), String> {
+        let kit = self.clone();
+        let state = subscription.state().clone();
+        self.block_on(async move { kit.unsubscribe_state(&state).await })?
+    }
+
+    // runs the future with the config's `spawn` and blocks until it's done,
+    // blocking inside a runtime could deadlock it so that's an error
+    #[cfg(all(feature = "subscriptions", feature = "sync"))]
+    pub(crate) fn block_on<T: Send + 'static>(
+        &self,
+        future: impl std::future::Future<Output = T> + Send + 'static,
+    ) -> Result<T, String> {
+        if tokio::runtime::Handle::try_current().is_ok() {
+            return Err("blocking calls can't be made from inside a runtime".into());
+        }
+        let (sender, receiver) = std::sync::mpsc::channel();
+        (self.config.spawn)(Box::pin(async move {
+            let _ = sender.send(future.await);
+        }))?;
+        receiver
+            .recv()
+            .map_err(|_| "subscription runtime stopped".to_string())
+    }
+
+    #[cfg(feature = "subscriptions")]
+    pub(crate) async fn update_filters(
+        &self,
+        subscription: &Arc<SubscriptionState>,
+        filters: Object,
+    ) -> Result<(), String> {
+        let socket = self.subscription_socket(subscription).await;
+        let channel = self
+            .request_subscription_channel(&subscription.model, &subscription.event, &filters)
+            .await?;
+        let old_channel = { subscription.channel.lock().await.clone() };
+        if channel == old_channel {
+            subscription.finish_switch(Some(filters)).await;
+            subscription.set_channel_requested((self.config.now)());
+            return Ok(());
+        }
+        self.wait_established(&socket).await?;
+        let socket_id = socket.get_socket_id().await;
+        let auth = self.authorize_subscription(&socket, &channel).await?;
+
+        subscription.begin_switch(channel.clone()).await;
+        socket
+            .add_subscription_channel(channel.clone(), subscription.clone())
+            .await;
+        // unsubscribing in the meantime didn't see the new channel
+        let res = if subscription.is_unsubscribed() {
+            Err("subscription was unsubscribed".to_string())
+        } else {
+            socket
+                .send(PusherProtocol::subscribe_frame(&channel, &auth))
+                .await
+                .map_err(String::from)
+        };
+        let res = match res {
+            Ok(()) => {
+                tokio::time::timeout(self.config.subscribe_timeout, subscription.wait_switch())
+                    .await
+                    .unwrap_or_else(|_| {
+                        Err("timed out waiting for subscription to succeed".to_string())
+                    })
+            },
+            Err(e) => Err(e),
+        };
+        if let Err(e) = res {
+            socket.remove_subscription_channel(channel).await;
+            subscription.finish_switch(None).await;
+            subscription.end_dedupe();
+            return Err(e);
+        }
+
+        // both channels are routed here until the old one is gone, so nothing
+        // is missed in between
+        subscription.finish_switch(Some(filters)).await;
+        subscription.set_channel_requested((self.config.now)());
+        subscription.set_auth(ChannelAuth {
+            channel,
+            socket_id,
+            auth,
+        });
+        socket
+            .remove_subscription_channel(old_channel.clone())
+            .await;
+        subscription.end_dedupe();
+        socket
+            .send(PusherProtocol::unsubscribe_frame(&old_channel))
+            .await
+            .map_err(String::from)
+    }
+
+    // also used by sockets to drop subscriptions that overflowed
+    #[cfg(feature = "subscriptions")]
+    pub async fn unsubscribe_state(
+        &self,
+        subscription: &Arc<SubscriptionState>,
+    ) -> Result<(), String> {
+        if !subscription.set_unsubscribed() {
+            return Ok(());
+        }
+        subscription.queue.close();
+        // a subscription that never got a connection has nothing to release
+        let socket = match subscription.socket() {
+            Some(socket) => socket,
+            None => return Ok(()),
+        };
+        if let Some(pool) = &self.config.socket_pool {
+            pool.release(&socket);
+        }
+        // a subscription that is moving channels is routed from both, the
+        // switch is abandoned
+        let mut channels = vec![subscription.channel.lock().await.clone()];
+        if let Some(next_channel) = subscription.next_channel().await {
+            subscription
+                .set_error(&next_channel, "subscription was unsubscribed".into())
+                .await;
+            channels.push(next_channel);
+        }
+        for channel in &channels {
+            socket.remove_subscription_channel(channel.clone()).await;
+        }
+        // the server forgets every subscription when the connection drops, so
+        // there's nothing to unsubscribe from if it isn't established
+        if !socket.get_established().is_set().await {
+            return Ok(());
+        }
+        for channel in &channels {
+            socket
+                .send(PusherProtocol::unsubscribe_frame(channel))
+                .await
+                .map_err(String::from)?;
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "subscriptions")]
+    pub async fn subscribe_request(
+        &self,
+        subscription: Arc<SubscriptionState>,
+    ) -> Result<(), String> {
+        let socket = self.subscription_socket(&subscription).await;
+        if !socket.get_connected().is_set().await {
+            socket.connect_ref().await?;
+            socket.start_ping_pong_task();
+        }
+
+        // a rejected channel may have expired, so it's requested again once
+        // unless it was just requested anyway
+        let mut refreshed = false;
+        loop {
+            let (auth, fresh) = self
+                .authorize_channel(&socket, &subscription, refreshed)
+                .await?;
+            refreshed |= fresh;
+
+            subscription.begin_subscribe().await;
+            socket.add_subscription(subscription.clone()).await;
+
+            if let Err(e) = socket
+                .send(PusherProtocol::subscribe_frame(&auth.channel, &auth.auth))
+                .await
+            {
+                socket.remove_subscription(subscription.clone()).await;
+                return Err(e.into());
+            }
+
+            let res = tokio::time::timeout(
+                self.config.subscribe_timeout,
+                subscription.wait_subscribed(),
+            )
+            .await;
+            match res {
+                Ok(Ok(())) => return Ok(()),
+                Ok(Err(_)) if !refreshed => {
+                    socket.remove_subscription(subscription.clone()).await;
+                    refreshed = true;
+                },
+                Ok(Err(e)) => {
+                    socket.remove_subscription(subscription.clone()).await;
+                    return Err(e);
+                },
+                Err(_) => {
+                    socket.remove_subscription(subscription.clone()).await;
+                    return Err("timed out waiting for subscription to succeed".to_string());
+                },
+            }
+        }
+    }
+
+    // authorizes the subscription's channel for the socket's connection,
+    // reusing the last auth if it's for the same channel and connection and
+    // requesting a new channel if the old one expired or was refused, also
+    // returns whether the channel was requested again
+    #[cfg(feature = "subscriptions")]
+    async fn authorize_channel(
+        &self,
+        socket: &Arc<dyn Socket>,
+        subscription: &SubscriptionState,
+        refresh: bool,
+    ) -> Result<(ChannelAuth, bool), String> {
+        let now = (self.config.now)();
+        let expired = matches!(
+            self.config.channel_lifetime,
+            Some(lifetime) if Duration::from_secs(subscription.channel_age(now)) >= lifetime
+        );
+        let mut refreshed = false;
+        if refresh || expired {
+            self.refresh_channel(socket, subscription).await?;
+            refreshed = true;
+        }
+
+        self.wait_established(socket).await?;
+        let socket_id = socket.get_socket_id().await;
         let mut channel = { subscription.channel.lock().await.clone() };
-        let auth = self.authorize_subscription(&channel).await;
-        if let Err(e) = &auth {
-            if e == "unauthorized" {
-                channel = self
-                    .request_subscription_channel(
-                        &subscription.model,
-                        &subscription.event,
-                        &subscription.filters,
-                    )
-                    .await?;
-                subscription.set_channel(channel.clone()).await;
-                let auth = self.authorize_subscription(&channel).await;
-                if let Err(e) = &auth {
-                    return Err(e.clone());
-                }
+        // a channel that was requested again may be the same one, but it's
+        // authorized again in case the old auth was what got refused
+        if let Some(auth) = subscription.auth().filter(|_| !refreshed) {
+            if auth.channel == channel && auth.socket_id == socket_id {
+                return Ok((auth, false));
             }
         }
-        let auth = auth.unwrap();
+        let auth = match self.authorize_subscription(socket, &channel).await {
+            Err(e) if e == "unauthorized" && !refreshed => {
+                self.refresh_channel(socket, subscription).await?;
+                refreshed = true;
+                channel = subscription.channel.lock().await.clone();
+                self.authorize_subscription(socket, &channel).await?
+            },
+            res => res?,
+        };
+        let auth = ChannelAuth {
+            channel,
+            socket_id,
+            auth,
+        };
+        subscription.set_auth(auth.clone());
+        Ok((auth, refreshed))
+    }
 
-        self.config
-            .socket
-            .add_subscription(subscription.clone())
-            .await;
+    // moves the subscription to a channel requested again for the same
+    // filters, like `update_filters` nothing is missed in between, a
+    // subscription that is already moving is left to it
+    #[cfg(feature = "subscriptions")]
+    pub(crate) async fn reauthorize(
+        &self,
+        subscription: &Arc<SubscriptionState>,
+    ) -> Result<(), String> {
+        if subscription.next_channel().await.is_some() {
+            return Ok(());
+        }
+        self.update_filters(subscription, subscription.filters())
+            .await
+    }
 
-        self.config
-            .socket
-            .send(
-                json!({
-                    "event": "pusher:subscribe",
-                    "data": {
-                        "channel": channel,
-                        "auth": auth.clone(),
+    // reauthorizes the subscription shortly before `channel_lifetime` is up
+    // until it's unsubscribed, a failed attempt is retried until the channel
+    // expires and then the subscription is closed and reported with
+    // `ConnectionEvent::SubscriptionFailed`
+    #[cfg(feature = "subscriptions")]
+    fn spawn_reauthorization(&self, subscription: &Arc<SubscriptionState>) {
+        let lifetime = match self.config.channel_lifetime {
+            Some(lifetime) => lifetime,
+            None => return,
+        };
+        let kit = self.clone();
+        let subscription = Arc::downgrade(subscription);
+        tokio::spawn(async move {
+            let margin = lifetime / 10;
+            let mut authorized = tokio::time::Instant::now();
+            let mut delay = lifetime - margin;
+            loop {
+                tokio::time::sleep(delay).await;
+                let subscription = match subscription.upgrade() {
+                    Some(subscription) if !subscription.is_unsubscribed() => subscription,
+                    _ => return,
+                };
+                let reason = match kit.reauthorize(&subscription).await {
+                    Ok(()) => {
+                        authorized = tokio::time::Instant::now();
+                        delay = lifetime - margin;
+                        continue;
+                    },
+                    Err(reason) => reason,
+                };
+                let socket = subscription.socket();
+                let channel = { subscription.channel.lock().await.clone() };
+                let left = lifetime.saturating_sub(authorized.elapsed());
+                if left.is_zero() {
+                    let _ = kit.unsubscribe_state(&subscription).await;
+                    if let Some(socket) = socket {
+                        socket.emit(ConnectionEvent::SubscriptionFailed { channel, reason });
                     }
-                })
-                .to_string(),
+                    return;
+                }
+                // the old channel still works until it expires
+                if let Some(socket) = socket {
+                    socket.emit(ConnectionEvent::Error(format!(
+                        "reauthorizing {} failed: {}",
+                        channel, reason
+                    )));
+                }
+                delay = margin.min(left);
+            }
+        });
+    }
+
+    // requests a new channel for the subscription's filters, the socket stops
+    // routing the old one
+    #[cfg(feature = "subscriptions")]
+    async fn refresh_channel(
+        &self,
+        socket: &Arc<dyn Socket>,
+        subscription: &SubscriptionState,
+    ) -> Result<(), String> {
+        let channel = self
+            .request_subscription_channel(
+                &subscription.model,
+                &subscription.event,
+                &subscription.filters(),
             )
             .await?;
+        let old_channel = { subscription.channel.lock().await.clone() };
+        if old_channel != channel {
+            socket.remove_subscription_channel(old_channel).await;
+            subscription.set_channel(channel).await;
+        }
+        subscription.set_channel_requested((self.config.now)());
+        Ok(())
+    }
 
-        let timeout =
-            tokio::time::timeout(Duration::from_secs(60), subscription.succeeded.wait()).await;
-        if timeout.is_err() {
-            self.config
-                .socket
-                .remove_subscription(subscription.clone())
-                .await;
-            return Err("timed out waiting for subscription to succeed".to_string());
+    // queries the records created since the last one the subscription
+    // received before it was last subscribed, at most `BACKFILL_MAX_PAGES`
+    // pages of them, the api can't be asked which records were updated or
+    // deleted so other events return an error
+    #[cfg(all(feature = "subscriptions", feature = "async"))]
+    pub async fn backfill(&self, subscription: &Arc<SubscriptionState>) -> Result<(), String> {
+        const BACKFILL_MAX_PAGES: usize = 10;
+        if !matches!(subscription.event, SubscriptionEvent::Create) {
+            return Err(format!(
+                "backfilling {} events isn't supported",
+                subscription.event.to_string()
+            ));
+        }
+        let (name, type_name) = match (
+            subscription.model.query_name(),
+            subscription.model.type_name(),
+        ) {
+            (Some(name), Some(type_name)) => (name, type_name),
+            _ => {
+                return Err(format!(
+                    "backfilling {} isn't supported",
+                    subscription.model.to_string()
+                ))
+            },
+        };
+        let watermark = match subscription.subscribed_watermark() {
+            Some(watermark) => watermark,
+            None => return Ok(()),
+        };
+        let known = self.type_fields(type_name).await?;
+        let mut field = field(name).set_argument("min_id".into(), (watermark + 1).into());
+        for filter in subscription.filters().iter() {
+            field = field.set_argument(filter.key().clone(), filter.value().clone());
+        }
+        // payloads list fields the api may not have (anymore)
+        for name in payload_fields(&subscription.model) {
+            if known.iter().any(|known| known == name) {
+                field = field.add_field_leaf(name);
+            }
+        }
+        let mut paginator =
+            Paginator::<Value>::new(Query::new(QueryType::Query).field(field.will_paginate()));
+        let mut records = Vec::new();
+        let mut pages = 0;
+        loop {
+            paginator.fill(self).await?;
+            pages += 1;
+            let mut filled = false;
+            while let Some((_, _, record)) = paginator.pop_raw() {
+                filled = true;
+                if let Some(record) = record.as_object() {
+                    records.push(record);
+                }
+            }
+            if !filled || paginator.finished() || pages >= BACKFILL_MAX_PAGES {
+                break;
+            }
         }
+        let channel = { subscription.channel.lock().await.clone() };
+        subscription
+            .push_backfill(channel, (self.config.now)(), records)
+            .await?;
+        if pages >= BACKFILL_MAX_PAGES && !paginator.finished() {
+            return Err(format!(
+                "backfill stopped after {} pages",
+                BACKFILL_MAX_PAGES
+            ));
+        }
+        Ok(())
+    }
 
-        Ok(subscription.clone())
+    // the names of the fields of a GraphQL type
+    #[cfg(all(feature = "subscriptions", feature = "async"))]
+    async fn type_fields(&self, type_name: &str) -> Result<Vec<String>, String> {
+        let query = Query::new(QueryType::Query).field(
+            field("__type")
+                .set_argument("name".into(), type_name.into())
+                .add_field_node(field("fields").add_field_leaf("name")),
+        );
+        let data = self.get(&query).await?.inner();
+        let fields = data
+            .get("__type")
+            .and_then(|t| t.value().as_object())
+            .and_then(|t| t.get("fields").and_then(|f| f.value().as_array()))
+            .ok_or_else(|| format!("unknown type {}", type_name))?;
+        Ok(fields
+            .iter()
+            .filter_map(|f| f.as_object())
+            .filter_map(|f| f.get("name").and_then(|name| name.value().as_string()))
+            .collect())
     }
 
     #[cfg(feature = "subscriptions")]
@@ -335,20 +961,8 @@ impl Kit {
         event: &SubscriptionEvent,
         filters: &Object,
     ) -> Result<String, String> {
-        let url = self
-            .config
-            .subscribe_url
-            .replace("{model}", &model.to_string())
-            .replace("{event}", &event.to_string());
-        let url = if !filters.is_empty() {
-            format!(
-                "{}?{}",
-                url,
-                serde_urlencoded::to_string(filters.to_query_string()).unwrap()
-            )
-        } else {
-            url
-        };
+        let url = SubscriptionFilter::from_object(model.clone(), filters)?
+            .url(&self.config.subscribe_url, event)?;
         let request = Request::new(
             Method::Get,
             url,
@@ -370,15 +984,52 @@ impl Kit {
         Err("malformed response".to_string())
     }
 
+    // waits at most the subscribe timeout for the socket's connection, and
+    // not at all for a socket that failed since it won't connect on its own
     #[cfg(feature = "subscriptions")]
-    async fn authorize_subscription(&self, channel: &String) -> Result<String, String> {
-        self.config.socket.get_established().wait().await;
+    async fn wait_established(&self, socket: &Arc<dyn Socket>) -> Result<(), String> {
+        let mut events = socket.connection_events();
+        if let ConnectionState::Failed(reason) = socket.get_state().await {
+            return Err(format!("socket failed: {}", reason));
+        }
+        let failed = async {
+            loop {
+                match events.recv().await {
+                    Ok(ConnectionEvent::Failed(reason)) => return reason,
+                    Err(broadcast::error::RecvError::Closed) => std::future::pending().await,
+                    _ => {},
+                }
+            }
+        };
+        let established = socket.get_established().wait();
+        futures_util::pin_mut!(failed, established);
+        match tokio::time::timeout(
+            self.config.subscribe_timeout,
+            futures_util::future::select(established, failed),
+        )
+        .await
+        {
+            Ok(futures_util::future::Either::Left(_)) => Ok(()),
+            Ok(futures_util::future::Either::Right((reason, _))) => {
+                Err(format!("socket failed: {}", reason))
+            },
+            Err(_) => Err("timed out waiting for the connection to be established".to_string()),
+        }
+    }
+
+    #[cfg(feature = "subscriptions")]
+    async fn authorize_subscription(
+        &self,
+        socket: &Arc<dyn Socket>,
+        channel: &String,
+    ) -> Result<String, String> {
+        self.wait_established(socket).await?;
         let request = Request::new(
             Method::Post,
             self.config.subscription_auth_url.clone(),
             Some(
                 serde_urlencoded::to_string([
-                    ("socket_id", &self.config.socket.get_socket_id().await),
+                    ("socket_id", &socket.get_socket_id().await),
                     ("channel_name", channel),
                 ])
                 .unwrap(),
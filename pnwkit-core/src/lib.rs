@@ -4,18 +4,34 @@ mod data;
 #[cfg(feature = "subscriptions")]
 mod event;
 mod field;
+#[cfg(feature = "subscriptions")]
+mod journal;
 mod kit;
+#[cfg(any(feature = "async", feature = "sync"))]
+mod multi_paginator;
 mod paginator;
+#[cfg(feature = "subscriptions")]
+mod payload;
+#[cfg(feature = "subscriptions")]
+mod protocol;
 mod query;
 mod rate_limiter;
+#[cfg(feature = "subscriptions")]
+mod reconnect;
 mod request;
 mod resolve;
+#[cfg(feature = "async")]
+mod sharded_paginator;
 #[cfg(feature = "subscriptions")]
 mod socket;
 #[cfg(feature = "subscriptions")]
 mod subscription;
 #[cfg(feature = "subscriptions")]
-mod to_query_string;
+mod subscription_filter;
+#[cfg(feature = "subscriptions")]
+mod subscription_set;
+#[cfg(test)]
+mod test_util;
 mod value;
 mod variable;
 
@@ -26,15 +42,44 @@ pub use data::{Data, Object};
 #[cfg(feature = "subscriptions")]
 pub use event::Event;
 pub use field::{field, field_as, Field, FieldType};
+#[cfg(feature = "subscriptions")]
+pub use journal::SubscriptionJournal;
 pub use kit::Kit;
-pub use paginator::Paginator;
+#[cfg(any(feature = "async", feature = "sync"))]
+pub use multi_paginator::MultiPaginator;
+pub use paginator::{Paginator, PaginatorCheckpoint, PaginatorInfo, PaginatorItemError};
+#[cfg(feature = "subscriptions")]
+pub use payload::{
+    AccountPayload, AlliancePayload, AlliancePositionPayload, BBGamePayload, BBTeamPayload,
+    BankrecPayload, BountyPayload, CityPayload, EmbargoPayload, NationPayload, SubscriptionPayload,
+    TaxBracketPayload, TradePayload, TradepricePayload, TreasureTradePayload, TreatyPayload,
+    WarAttackPayload, WarPayload,
+};
+#[cfg(feature = "subscriptions")]
+pub use protocol::{ProtocolAction, PusherProtocol};
 pub use rate_limiter::RateLimiter;
+#[cfg(feature = "subscriptions")]
+pub use reconnect::ReconnectPolicy;
 pub use request::{Client, Headers, Method, Request, Response, ResponseResult};
 #[cfg(feature = "subscriptions")]
 pub use serde_json::{from_str as json_from_str, json};
+#[cfg(feature = "async")]
+pub use sharded_paginator::ShardedPaginator;
+#[cfg(feature = "subscriptions")]
+pub use socket::{
+    ConnectionEvent, ConnectionState, OutgoingQueue, SendError, Socket, SocketAssignment,
+    SocketPool, SocketStatus,
+};
+#[cfg(all(feature = "subscriptions", feature = "sync"))]
+pub use subscription::BlockingIter;
+#[cfg(feature = "subscriptions")]
+pub use subscription::{
+    ChannelAuth, OverflowPolicy, QueueLimit, Subscription, SubscriptionEvent, SubscriptionMessage,
+    SubscriptionModel, SubscriptionReceiver, SubscriptionState, TypedSubscription,
+};
 #[cfg(feature = "subscriptions")]
-pub use socket::Socket;
+pub use subscription_filter::SubscriptionFilter;
 #[cfg(feature = "subscriptions")]
-pub use subscription::{Subscription, SubscriptionEvent, SubscriptionModel};
+pub use subscription_set::SubscriptionSet;
 pub use value::Value;
 pub use variable::{variable, Variable, VariableType};
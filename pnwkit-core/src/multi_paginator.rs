@@ -0,0 +1,276 @@
+use std::collections::VecDeque;
+
+use crate::{
+    paginator::PaginatorInfo,
+    query::{Query, QueryType},
+    variable::Variables,
+    Field, Kit, Object, Value, VariableType,
+};
+
+#[derive(Debug)]
+struct PaginatedField {
+    field: Field,
+    key: String,
+    page_variable: String,
+    paginator_info: Option<PaginatorInfo>,
+    queue: VecDeque<Value>,
+}
+
+impl PaginatedField {
+    fn new(mut field: Field) -> Self {
+        field.paginate = true;
+        field.paginate_name = false;
+        let key = field.alias.clone().unwrap_or_else(|| field.name.clone());
+        let page_variable = format!("__page_{}", key);
+        field.arguments.insert(
+            "page".into(),
+            crate::variable(&page_variable, VariableType::Int).into(),
+        );
+        Self {
+            field,
+            key,
+            page_variable,
+            paginator_info: None,
+            queue: VecDeque::new(),
+        }
+    }
+
+    fn exhausted(&self) -> bool {
+        matches!(&self.paginator_info, Some(info) if !info.has_more_pages())
+    }
+
+    fn result(&mut self, result: &Value) {
+        if let Value::Object(o) = result {
+            let paginator_info = o.get("paginatorInfo").unwrap();
+            let paginator_info = paginator_info.value();
+            if let Some(p) = &mut self.paginator_info {
+                p.update(paginator_info)
+            } else {
+                self.paginator_info = Some(paginator_info.into())
+            }
+
+            let data = o.get("data").unwrap();
+            let data = data.value();
+            if let Value::Array(l) = data {
+                for i in l.iter() {
+                    self.queue.push_back(i.clone());
+                }
+            }
+        };
+    }
+}
+
+#[derive(Debug)]
+pub struct MultiPaginator {
+    fields: Vec<PaginatedField>,
+    variables: Variables,
+}
+
+impl MultiPaginator {
+    pub fn new() -> Self {
+        Self::with_variables(Variables::new())
+    }
+
+    pub fn with_variables(variables: Variables) -> Self {
+        Self {
+            fields: Vec::new(),
+            variables,
+        }
+    }
+
+    // each field is keyed by its alias, or its name if it doesn't have one, so
+    // fields with the same name need distinct aliases
+    pub fn field(mut self, field: Field) -> Self {
+        let field = PaginatedField::new(field);
+        self.variables.set(field.page_variable.clone(), 0.into());
+        self.fields.push(field);
+        self
+    }
+
+    pub fn paginator_info(&self, key: &str) -> Option<&PaginatorInfo> {
+        self.fields
+            .iter()
+            .find(|f| f.key == key)
+            .and_then(|f| f.paginator_info.as_ref())
+    }
+
+    pub fn exhausted(&self, key: &str) -> bool {
+        self.fields
+            .iter()
+            .any(|f| f.key == key && f.exhausted() && f.queue.is_empty())
+    }
+
+    #[cfg(feature = "async")]
+    pub async fn next(&mut self, kit: &Kit) -> Option<(String, Value)> {
+        if self.is_empty() && (self.fill(kit).await).is_err() {
+            return None;
+        }
+        self.pop()
+    }
+
+    #[cfg(feature = "sync")]
+    pub fn next_sync(&mut self, kit: &Kit) -> Option<(String, Value)> {
+        if self.is_empty() && self.fill_sync(kit).is_err() {
+            return None;
+        }
+        self.pop()
+    }
+
+    #[cfg(feature = "async")]
+    pub async fn fill(&mut self, kit: &Kit) -> Result<(), String> {
+        let query = match self.page()? {
+            Some(query) => query,
+            None => return Ok(()),
+        };
+        let result = kit.get_with_variables(&query, &self.variables).await?;
+        self.result(result.inner());
+        Ok(())
+    }
+
+    #[cfg(feature = "sync")]
+    pub fn fill_sync(&mut self, kit: &Kit) -> Result<(), String> {
+        let query = match self.page()? {
+            Some(query) => query,
+            None => return Ok(()),
+        };
+        let result = kit.get_with_variables_sync(&query, &self.variables)?;
+        self.result(result.inner());
+        Ok(())
+    }
+
+    fn is_empty(&self) -> bool {
+        self.fields.iter().all(|f| f.queue.is_empty())
+    }
+
+    fn pop(&mut self) -> Option<(String, Value)> {
+        self.fields
+            .iter_mut()
+            .find_map(|f| f.queue.pop_front().map(|v| (f.key.clone(), v)))
+    }
+
+    // builds a query with only the fields that still have pages left
+    fn page(&self) -> Result<Option<Query>, String> {
+        let mut query = Query::new(QueryType::Query);
+        for field in self.fields.iter().filter(|f| !f.exhausted()) {
+            let page = match self.variables.get(field.page_variable.clone()) {
+                Some(Value::Int(i)) => i,
+                _ => return Err("invalid paginator variable".into()),
+            };
+            self.variables
+                .set(field.page_variable.clone(), (page + 1).into());
+            query = query.field(field.field.clone());
+        }
+        if query.fields.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(query))
+        }
+    }
+
+    fn result(&mut self, result: Object) {
+        for field in self.fields.iter_mut().filter(|f| !f.exhausted()) {
+            if let Some(value) = result.get(&field.key) {
+                field.result(value.value());
+            }
+            if field.exhausted() {
+                self.variables.0.remove(&field.page_variable);
+            }
+        }
+    }
+}
+
+impl Default for MultiPaginator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(all(test, feature = "async"))]
+mod tests {
+    use serde_json::json;
+
+    use crate::{
+        field, field_as,
+        test_util::{block_on, body, id, kit, ok, paginated, variable, MockClient},
+    };
+
+    // nations have two pages and alliances one
+    fn client() -> MockClient {
+        MockClient::new(|request| {
+            let body = body(request);
+            let mut data = serde_json::Map::new();
+            if let Some(page) = variable(&body, "__page_nations").as_i64() {
+                data.insert(
+                    "nations".into(),
+                    paginated(vec![json!({ "id": page })], page as i32, page < 2),
+                );
+            }
+            if let Some(page) = variable(&body, "__page_top").as_i64() {
+                data.insert(
+                    "top".into(),
+                    paginated(vec![json!({ "id": 100 + page })], page as i32, false),
+                );
+            }
+            ok(json!({ "data": data }))
+        })
+    }
+
+    #[test]
+    fn fields_are_paged_by_their_own_variables() {
+        let client = client();
+        let kit = kit(client.clone());
+        let mut paginator = kit
+            .multi_paginator()
+            .field(field("nations").add_field_leaf("id"))
+            .field(field_as("alliances", "top").add_field_leaf("id"));
+        let items = block_on(async {
+            let mut items = Vec::new();
+            while let Some((key, item)) = paginator.next(&kit).await {
+                items.push((key, id(&item)));
+            }
+            items
+        });
+        assert_eq!(
+            items,
+            vec![
+                ("nations".to_string(), 1),
+                ("top".to_string(), 101),
+                ("nations".to_string(), 2),
+            ]
+        );
+        assert!(paginator.exhausted("nations"));
+        assert!(paginator.exhausted("top"));
+        assert_eq!(
+            paginator.paginator_info("nations").unwrap().current_page(),
+            2
+        );
+
+        let bodies = client.bodies();
+        assert_eq!(bodies.len(), 2);
+        assert_eq!(variable(&bodies[0], "__page_nations"), json!(1));
+        assert_eq!(variable(&bodies[0], "__page_top"), json!(1));
+        let query = bodies[0]["query"].as_str().unwrap();
+        assert!(query.contains("$__page_nations: Int"));
+        assert!(query.contains("top:alliances(page: $__page_top)"));
+        // the exhausted field is left out of the next query
+        assert_eq!(variable(&bodies[1], "__page_nations"), json!(2));
+        assert!(bodies[1]["variables"].get("__page_top").is_none());
+        assert!(!bodies[1]["query"].as_str().unwrap().contains("alliances"));
+    }
+
+    #[cfg(feature = "sync")]
+    #[test]
+    fn sync_pages_the_same_way() {
+        let client = client();
+        let kit = kit(client.clone());
+        let mut paginator = kit
+            .multi_paginator()
+            .field(field("nations").add_field_leaf("id"));
+        let mut ids = Vec::new();
+        while let Some((_, item)) = paginator.next_sync(&kit) {
+            ids.push(id(&item));
+        }
+        assert_eq!(ids, vec![1, 2]);
+        assert_eq!(client.bodies().len(), 2);
+    }
+}
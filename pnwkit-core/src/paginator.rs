@@ -1,11 +1,13 @@
-use std::collections::VecDeque;
+use std::{collections::VecDeque, fmt::Display, marker::PhantomData};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 
 #[cfg(any(feature = "async", feature = "sync"))]
 use crate::Kit;
 
-use crate::{query::Query, variable::Variables, Value};
+use crate::{query::Query, variable::Variables, Object, Value};
 
-#[derive(Debug)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct PaginatorInfo {
     count: i32,
     current_page: i32,
@@ -18,7 +20,39 @@ pub struct PaginatorInfo {
 }
 
 impl PaginatorInfo {
-    fn update(&mut self, value: &Value) {
+    pub fn count(&self) -> i32 {
+        self.count
+    }
+
+    pub fn current_page(&self) -> i32 {
+        self.current_page
+    }
+
+    pub fn first_item(&self) -> i32 {
+        self.first_item
+    }
+
+    pub fn has_more_pages(&self) -> bool {
+        self.has_more_pages
+    }
+
+    pub fn last_item(&self) -> i32 {
+        self.last_item
+    }
+
+    pub fn last_page(&self) -> i32 {
+        self.last_page
+    }
+
+    pub fn per_page(&self) -> i32 {
+        self.per_page
+    }
+
+    pub fn total(&self) -> i32 {
+        self.total
+    }
+
+    pub(crate) fn update(&mut self, value: &Value) {
         if let Value::Object(o) = value {
             for i in o.iter() {
                 let (k, v) = i.pair();
@@ -55,75 +89,223 @@ impl From<&Value> for PaginatorInfo {
     }
 }
 
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct PaginatorCheckpoint {
+    pub query: Query,
+    pub variables: Object,
+    pub page: i32,
+    pub paginator_info: Option<PaginatorInfo>,
+    // items that had been fetched but not returned yet, along with the page
+    // and index they came from
+    pub queue: Vec<(i32, usize, Value)>,
+    pub end_page: Option<i32>,
+    pub max_items: Option<usize>,
+    pub max_pages: Option<usize>,
+    pub items: usize,
+    pub pages: usize,
+}
+
+#[derive(Clone, Debug)]
+pub struct PaginatorItemError {
+    pub page: i32,
+    pub index: usize,
+    pub message: String,
+}
+
+impl Display for PaginatorItemError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "failed to deserialize item {} on page {}: {}",
+            self.index, self.page, self.message
+        )
+    }
+}
+
+impl std::error::Error for PaginatorItemError {}
+
 #[derive(Debug)]
-pub struct Paginator {
+pub struct Paginator<T = Value> {
     pub paginator_info: Option<PaginatorInfo>,
     query: Query,
     variables: Variables,
-    queue: VecDeque<Value>,
+    queue: VecDeque<(i32, usize, Value)>,
+    end_page: Option<i32>,
+    max_items: Option<usize>,
+    max_pages: Option<usize>,
+    items: usize,
+    pages: usize,
+    _item: PhantomData<T>,
 }
 
-impl Paginator {
+impl<T: DeserializeOwned + 'static> Paginator<T> {
     pub fn new(query: Query) -> Self {
-        let variables = Variables::with_capacity(1);
-        variables.set("__page".into(), 0.into());
-        Self {
-            paginator_info: None,
-            query,
-            variables,
-            queue: VecDeque::new(),
-        }
+        Self::from_parts(query, Variables::with_capacity(1), VecDeque::new())
     }
 
     pub fn with_capacity(query: Query, capacity: u16) -> Self {
-        let variables = Variables::with_capacity(1);
-        variables.set("__page".into(), 0.into());
-        Self {
-            paginator_info: None,
+        Self::from_parts(
             query,
-            variables,
-            queue: VecDeque::with_capacity(capacity as usize),
-        }
+            Variables::with_capacity(1),
+            VecDeque::with_capacity(capacity as usize),
+        )
     }
 
     pub fn with_variables(query: Query, variables: Variables) -> Self {
+        Self::from_parts(query, variables, VecDeque::new())
+    }
+
+    pub fn with_capacity_and_variables(query: Query, capacity: u16, variables: Variables) -> Self {
+        Self::from_parts(query, variables, VecDeque::with_capacity(capacity as usize))
+    }
+
+    fn from_parts(
+        query: Query,
+        variables: Variables,
+        queue: VecDeque<(i32, usize, Value)>,
+    ) -> Self {
         variables.set("__page".into(), 0.into());
         Self {
             paginator_info: None,
             query,
             variables,
-            queue: VecDeque::new(),
+            queue,
+            end_page: None,
+            max_items: None,
+            max_pages: None,
+            items: 0,
+            pages: 0,
+            _item: PhantomData,
         }
     }
 
-    pub fn with_capacity_and_variables(query: Query, capacity: u16, variables: Variables) -> Self {
-        variables.set("__page".into(), 0.into());
+    pub fn set_per_page(self, per_page: u16) -> Self {
+        for field in &self.query.fields {
+            field.set_paginate_argument("first".into(), per_page.into());
+        }
+        self
+    }
+
+    pub fn set_start_page(self, start_page: i32) -> Self {
+        self.variables
+            .set("__page".into(), (start_page.max(1) - 1).into());
+        self
+    }
+
+    pub fn set_end_page(mut self, end_page: i32) -> Self {
+        self.end_page = Some(end_page);
+        self
+    }
+
+    pub fn set_max_items(mut self, max_items: usize) -> Self {
+        self.max_items = Some(max_items);
+        self
+    }
+
+    pub fn set_max_pages(mut self, max_pages: usize) -> Self {
+        self.max_pages = Some(max_pages);
+        self
+    }
+
+    pub fn from_checkpoint(checkpoint: PaginatorCheckpoint) -> Self {
+        let variables = Variables(checkpoint.variables);
+        variables.set("__page".into(), checkpoint.page.into());
         Self {
-            paginator_info: None,
-            query,
+            paginator_info: checkpoint.paginator_info,
+            query: checkpoint.query,
             variables,
-            queue: VecDeque::with_capacity(capacity as usize),
+            queue: checkpoint.queue.into(),
+            end_page: checkpoint.end_page,
+            max_items: checkpoint.max_items,
+            max_pages: checkpoint.max_pages,
+            items: checkpoint.items,
+            pages: checkpoint.pages,
+            _item: PhantomData,
+        }
+    }
+
+    pub fn items(&self) -> usize {
+        self.items
+    }
+
+    pub fn pages(&self) -> usize {
+        self.pages
+    }
+
+    pub fn checkpoint(&self) -> PaginatorCheckpoint {
+        let variables = self.variables.0.clone();
+        let page = match variables.remove("__page") {
+            Some((_, Value::Int(i))) => i as i32,
+            _ => 0,
+        };
+        PaginatorCheckpoint {
+            query: self.query.clone(),
+            variables,
+            page,
+            paginator_info: self.paginator_info.clone(),
+            queue: self.queue.iter().cloned().collect(),
+            end_page: self.end_page,
+            max_items: self.max_items,
+            max_pages: self.max_pages,
+            items: self.items,
+            pages: self.pages,
         }
     }
 
     #[cfg(feature = "async")]
-    pub async fn next(&mut self, kit: &Kit) -> Option<Value> {
+    pub async fn next(&mut self, kit: &Kit) -> Option<Result<T, PaginatorItemError>> {
+        if self.items_exhausted() {
+            return None;
+        }
         if self.queue.is_empty() && (self.fill(kit).await).is_err() {
             return None;
         }
-        self.queue.pop_front()
+        self.pop()
     }
 
     #[cfg(feature = "sync")]
-    pub fn next_sync(&mut self, kit: &Kit) -> Option<Value> {
+    pub fn next_sync(&mut self, kit: &Kit) -> Option<Result<T, PaginatorItemError>> {
+        if self.items_exhausted() {
+            return None;
+        }
         if self.queue.is_empty() && self.fill_sync(kit).is_err() {
             return None;
         }
-        self.queue.pop_front()
+        self.pop()
+    }
+
+    #[cfg(any(feature = "async", feature = "sync"))]
+    fn pop(&mut self) -> Option<Result<T, PaginatorItemError>> {
+        let (page, index, value) = self.pop_raw()?;
+        Some(deserialize_item(page, index, value))
+    }
+
+    #[cfg(any(feature = "async", feature = "sync"))]
+    pub(crate) fn pop_raw(&mut self) -> Option<(i32, usize, Value)> {
+        let item = self.queue.pop_front()?;
+        self.items += 1;
+        Some(item)
+    }
+
+    #[cfg(feature = "async")]
+    pub(crate) fn is_empty(&self) -> bool {
+        self.queue.is_empty()
     }
 
+    #[cfg(feature = "async")]
+    pub(crate) fn finished(&self) -> bool {
+        self.queue.is_empty() && matches!(&self.paginator_info, Some(info) if !info.has_more_pages)
+    }
+
+    #[cfg(any(feature = "async", feature = "sync"))]
+    fn items_exhausted(&self) -> bool {
+        matches!(self.max_items, Some(max_items) if self.items >= max_items)
+    }
+
+    // a page that failed is requested again by the next fill
     #[cfg(feature = "async")]
     pub async fn fill(&mut self, kit: &Kit) -> Result<(), String> {
+        let previous = self.variables.get("__page".into());
         match self.page() {
             Ok(end) => {
                 if end {
@@ -132,7 +314,13 @@ impl Paginator {
             },
             Err(e) => return Err(e),
         }
-        let result = kit.get_with_variables(&self.query, &self.variables).await?;
+        let result = match kit.get_with_variables(&self.query, &self.variables).await {
+            Ok(result) => result,
+            Err(e) => {
+                self.restore_page(previous);
+                return Err(e);
+            },
+        };
         let result = result.inner();
         let result = result.get("__paginate").unwrap();
         self.result(result.value());
@@ -141,6 +329,7 @@ impl Paginator {
 
     #[cfg(feature = "sync")]
     pub fn fill_sync(&mut self, kit: &Kit) -> Result<(), String> {
+        let previous = self.variables.get("__page".into());
         match self.page() {
             Ok(end) => {
                 if end {
@@ -149,18 +338,36 @@ impl Paginator {
             },
             Err(e) => return Err(e),
         }
-        let result = kit.get_with_variables_sync(&self.query, &self.variables)?;
+        let result = match kit.get_with_variables_sync(&self.query, &self.variables) {
+            Ok(result) => result,
+            Err(e) => {
+                self.restore_page(previous);
+                return Err(e);
+            },
+        };
         let result = result.inner();
         let result = result.get("__paginate").unwrap();
         self.result(result.value());
         Ok(())
     }
 
+    #[cfg(any(feature = "async", feature = "sync"))]
+    fn restore_page(&self, page: Option<Value>) {
+        if let Some(page) = page {
+            self.variables.set("__page".into(), page);
+        }
+    }
+
     #[cfg(any(feature = "async", feature = "sync"))]
     fn page(&self) -> Result<bool, String> {
         if self.paginator_info.is_some() && !self.paginator_info.as_ref().unwrap().has_more_pages {
             return Ok(true);
         }
+        if self.items_exhausted()
+            || matches!(self.max_pages, Some(max_pages) if self.pages >= max_pages)
+        {
+            return Ok(true);
+        }
         let page = self.variables.get("__page".into()).unwrap();
         let page = match page {
             Value::Int(i) => i as i32,
@@ -172,6 +379,9 @@ impl Paginator {
                 }
             },
         };
+        if matches!(self.end_page, Some(end_page) if page >= end_page) {
+            return Ok(true);
+        }
         self.variables.set("__page".into(), (page + 1).into());
         Ok(false)
     }
@@ -186,14 +396,254 @@ impl Paginator {
             } else {
                 self.paginator_info = Some(paginator_info.into())
             }
+            self.pages += 1;
 
+            let page = match self.variables.get("__page".into()) {
+                Some(Value::Int(i)) => i as i32,
+                _ => self.paginator_info.as_ref().unwrap().current_page,
+            };
             let data = o.get("data").unwrap();
             let data = data.value();
             if let Value::Array(l) = data {
-                for i in l.iter() {
-                    self.queue.push_back(i.clone());
+                for (index, i) in l.iter().enumerate() {
+                    self.queue.push_back((page, index, i.clone()));
                 }
             }
         };
     }
 }
+
+#[cfg(any(feature = "async", feature = "sync"))]
+pub(crate) fn deserialize_item<T: DeserializeOwned + 'static>(
+    page: i32,
+    index: usize,
+    value: Value,
+) -> Result<T, PaginatorItemError> {
+    // items are values already, so only other types go through serde
+    let value = match (Box::new(value) as Box<dyn std::any::Any>).downcast::<T>() {
+        Ok(item) => return Ok(*item),
+        Err(value) => value.downcast::<Value>().unwrap(),
+    };
+    serde_json::to_value(*value)
+        .and_then(serde_json::from_value)
+        .map_err(|e| PaginatorItemError {
+            page,
+            index,
+            message: e.to_string(),
+        })
+}
+
+#[cfg(all(test, feature = "async"))]
+mod tests {
+    use serde_json::json;
+
+    use crate::{
+        field,
+        test_util::{
+            block_on, body, counting_page, id, kit, page, page_number, variable, MockClient,
+        },
+        Paginator, PaginatorCheckpoint,
+    };
+
+    fn nations() -> MockClient {
+        MockClient::new(|request| counting_page(1, page_number(&body(request)), 3))
+    }
+
+    #[test]
+    fn checkpoint_round_trips_through_json() {
+        let client = nations();
+        let kit = kit(client.clone());
+        let mut paginator = kit.paginator(field("nations").will_paginate().add_field_leaf("id"));
+        let mut ids = Vec::new();
+        block_on(async {
+            let item = paginator.next(&kit).await.unwrap().unwrap();
+            ids.push(id(&item));
+        });
+
+        let checkpoint = serde_json::to_string(&paginator.checkpoint()).unwrap();
+        let checkpoint = serde_json::from_str::<PaginatorCheckpoint>(&checkpoint).unwrap();
+        assert_eq!(checkpoint.page, 1);
+        assert_eq!(checkpoint.queue.len(), 1);
+        assert_eq!(checkpoint.items, 1);
+        assert_eq!(checkpoint.pages, 1);
+
+        let mut paginator = kit.paginator_from_checkpoint(checkpoint);
+        block_on(async {
+            while let Some(item) = paginator.next(&kit).await {
+                ids.push(id(&item.unwrap()));
+            }
+        });
+        assert_eq!(ids, vec![1, 2, 3, 4, 5, 6]);
+        let pages = client
+            .bodies()
+            .iter()
+            .map(|b| variable(b, "__page"))
+            .collect::<Vec<_>>();
+        assert_eq!(pages, vec![json!(1), json!(2), json!(3)]);
+    }
+
+    #[test]
+    fn checkpoint_keeps_limits_and_variables() {
+        let kit = kit(nations());
+        let variables = crate::variable::Variables::new();
+        variables.set("alliance".into(), 7.into());
+        let paginator = Paginator::<crate::Value>::with_variables(
+            kit.query().field(field("nations").will_paginate()),
+            variables,
+        )
+        .set_start_page(4)
+        .set_end_page(9)
+        .set_max_items(20)
+        .set_max_pages(5);
+        let checkpoint = serde_json::to_value(paginator.checkpoint()).unwrap();
+        let checkpoint = serde_json::from_value::<PaginatorCheckpoint>(checkpoint).unwrap();
+        assert_eq!(checkpoint.page, 3);
+        assert_eq!(checkpoint.end_page, Some(9));
+        assert_eq!(checkpoint.max_items, Some(20));
+        assert_eq!(checkpoint.max_pages, Some(5));
+        assert_eq!(
+            checkpoint.variables.get("alliance").unwrap().as_i64(),
+            Some(7)
+        );
+        assert!(checkpoint.variables.get("__page").is_none());
+    }
+
+    fn ids(paginator: &mut Paginator, kit: &crate::Kit) -> Vec<i64> {
+        block_on(async {
+            let mut ids = Vec::new();
+            while let Some(item) = paginator.next(kit).await {
+                ids.push(id(&item.unwrap()));
+            }
+            ids
+        })
+    }
+
+    fn pages(client: &MockClient) -> Vec<serde_json::Value> {
+        client
+            .bodies()
+            .iter()
+            .map(|b| variable(b, "__page"))
+            .collect()
+    }
+
+    #[test]
+    fn per_page_is_sent_as_first() {
+        let client = nations();
+        let kit = kit(client.clone());
+        let mut paginator = kit
+            .paginator(field("nations").will_paginate().add_field_leaf("id"))
+            .set_per_page(2);
+        assert_eq!(ids(&mut paginator, &kit).len(), 6);
+        for body in client.bodies() {
+            assert!(body["query"].as_str().unwrap().contains("first: 2"));
+        }
+    }
+
+    #[test]
+    fn start_and_end_page_bound_the_requests() {
+        let client = nations();
+        let kit = kit(client.clone());
+        let mut paginator = kit
+            .paginator(field("nations").will_paginate().add_field_leaf("id"))
+            .set_start_page(2)
+            .set_end_page(2);
+        assert_eq!(ids(&mut paginator, &kit), vec![3, 4]);
+        assert_eq!(pages(&client), vec![json!(2)]);
+    }
+
+    #[test]
+    fn max_items_stops_mid_page() {
+        let client = nations();
+        let kit = kit(client.clone());
+        let mut paginator = kit
+            .paginator(field("nations").will_paginate().add_field_leaf("id"))
+            .set_max_items(3);
+        assert_eq!(ids(&mut paginator, &kit), vec![1, 2, 3]);
+        assert_eq!(paginator.items(), 3);
+        assert_eq!(pages(&client), vec![json!(1), json!(2)]);
+    }
+
+    #[test]
+    fn max_pages_stops_requesting() {
+        let client = nations();
+        let kit = kit(client.clone());
+        let mut paginator = kit
+            .paginator(field("nations").will_paginate().add_field_leaf("id"))
+            .set_max_pages(2);
+        assert_eq!(ids(&mut paginator, &kit), vec![1, 2, 3, 4]);
+        assert_eq!(paginator.pages(), 2);
+        assert_eq!(pages(&client), vec![json!(1), json!(2)]);
+    }
+
+    #[cfg(feature = "sync")]
+    #[test]
+    fn sync_respects_the_same_limits() {
+        let client = nations();
+        let kit = kit(client.clone());
+        let mut paginator = kit
+            .paginator(field("nations").will_paginate().add_field_leaf("id"))
+            .set_start_page(2)
+            .set_max_items(3);
+        let mut ids = Vec::new();
+        while let Some(item) = paginator.next_sync(&kit) {
+            ids.push(id(&item.unwrap()));
+        }
+        assert_eq!(ids, vec![3, 4, 5]);
+        assert_eq!(pages(&client), vec![json!(2), json!(3)]);
+    }
+
+    #[derive(Debug, serde::Deserialize, PartialEq)]
+    struct Nation {
+        id: i64,
+        name: String,
+    }
+
+    #[test]
+    fn items_deserialize_into_the_item_type() {
+        let kit = kit(MockClient::new(|_| {
+            page(
+                vec![
+                    json!({ "id": 1, "name": "a" }),
+                    json!({ "id": "two" }),
+                    json!({ "id": 3, "name": "c" }),
+                ],
+                1,
+                false,
+            )
+        }));
+        let mut paginator = kit.paginator_as::<Nation>(field("nations").will_paginate());
+        let items = block_on(async {
+            let mut items = Vec::new();
+            while let Some(item) = paginator.next(&kit).await {
+                items.push(item);
+            }
+            items
+        });
+        assert_eq!(items.len(), 3);
+        assert_eq!(
+            items[0].as_ref().unwrap(),
+            &Nation {
+                id: 1,
+                name: "a".into()
+            }
+        );
+        // a bad item doesn't end the pagination
+        let err = items[1].as_ref().unwrap_err();
+        assert_eq!((err.page, err.index), (1, 1));
+        let err: Box<dyn std::error::Error> = Box::new(err.clone());
+        assert!(err
+            .to_string()
+            .starts_with("failed to deserialize item 1 on page 1"));
+        assert_eq!(items[2].as_ref().unwrap().id, 3);
+    }
+
+    #[test]
+    fn value_items_are_returned_as_is() {
+        let value = crate::Value::Array(vec![1.into(), crate::Value::None]);
+        let item = super::deserialize_item::<crate::Value>(1, 0, value).unwrap();
+        assert!(matches!(
+            item.as_array().as_deref(),
+            Some([crate::Value::Int(1), crate::Value::None])
+        ));
+    }
+}
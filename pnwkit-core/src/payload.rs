@@ -0,0 +1,503 @@
+use crate::{Object, SubscriptionModel};
+
+pub trait SubscriptionPayload: Sized + Send + 'static {
+    fn model() -> SubscriptionModel;
+
+    fn from_object(object: &Object) -> Self;
+}
+
+// every field is optional and converted leniently through `Value` so that
+// payloads keep working when fields are added, removed or change between
+// numbers and strings, fields that aren't listed are ignored
+macro_rules! payload {
+    ($($name:ident, $model:ident { $($field:ident: $ty:ident,)* })*) => {
+        $(
+            #[derive(Clone, Debug, Default)]
+            pub struct $name {
+                $(pub $field: Option<$ty>,)*
+            }
+
+            impl SubscriptionPayload for $name {
+                fn model() -> SubscriptionModel {
+                    SubscriptionModel::$model
+                }
+
+                fn from_object(object: &Object) -> Self {
+                    Self {
+                        $(
+                            $field: object
+                                .get(stringify!($field).trim_start_matches("r#"))
+                                .and_then(|v| paste::paste! { v.value().[<as_ $ty:lower>]() }),
+                        )*
+                    }
+                }
+            }
+        )*
+
+        #[cfg(feature = "async")]
+        pub(crate) fn payload_fields(model: &SubscriptionModel) -> Vec<&'static str> {
+            match model {
+                $(
+                    SubscriptionModel::$model => vec![
+                        $(stringify!($field).trim_start_matches("r#"),)*
+                    ],
+                )*
+            }
+        }
+    };
+}
+
+payload!(
+    AccountPayload, Account {
+        id: i32,
+        last_active: String,
+        discord_id: String,
+    }
+
+    AlliancePayload, Alliance {
+        id: i32,
+        name: String,
+        acronym: String,
+        score: f64,
+        color: String,
+        date: String,
+        average_score: f64,
+        accept_members: bool,
+        flag: String,
+        forum_link: String,
+        discord_link: String,
+        wiki_link: String,
+        rank: i32,
+    }
+
+    AlliancePositionPayload, AlliancePosition {
+        id: i32,
+        date: String,
+        alliance_id: i32,
+        name: String,
+        creator_id: i32,
+        last_editor_id: i32,
+        date_modified: String,
+        position_level: i32,
+        leader: bool,
+        heir: bool,
+        officer: bool,
+        member: bool,
+        permissions: i64,
+    }
+
+    BankrecPayload, Bankrec {
+        id: i32,
+        date: String,
+        sender_id: i32,
+        sender_type: i32,
+        receiver_id: i32,
+        receiver_type: i32,
+        banker_id: i32,
+        note: String,
+        money: f64,
+        coal: f64,
+        oil: f64,
+        uranium: f64,
+        iron: f64,
+        bauxite: f64,
+        lead: f64,
+        gasoline: f64,
+        munitions: f64,
+        steel: f64,
+        aluminum: f64,
+        food: f64,
+        tax_id: i32,
+    }
+
+    BBGamePayload, BBGame {
+        id: i32,
+        date: String,
+        home_id: i32,
+        away_id: i32,
+        home_nation_id: i32,
+        away_nation_id: i32,
+        stadium_name: String,
+        home_score: i32,
+        away_score: i32,
+        highlights: String,
+        home_revenue: f64,
+        spoils: f64,
+        open: i32,
+        wager: f64,
+    }
+
+    BBTeamPayload, BBTeam {
+        id: i32,
+        date: String,
+        nation_id: i32,
+        name: String,
+        logo: String,
+        home_stadium: String,
+        quality: i32,
+        seating: i32,
+        rating: f64,
+        wins: i32,
+        glosses: i32,
+        runs: i32,
+        homers: i32,
+        strikeouts: i32,
+        games_played: i32,
+    }
+
+    BountyPayload, Bounty {
+        id: i32,
+        date: String,
+        nation_id: i32,
+        amount: i64,
+        r#type: String,
+    }
+
+    CityPayload, City {
+        id: i32,
+        nation_id: i32,
+        name: String,
+        date: String,
+        infrastructure: f64,
+        land: f64,
+        powered: bool,
+        oil_power: i32,
+        wind_power: i32,
+        coal_power: i32,
+        nuclear_power: i32,
+        coal_mine: i32,
+        oil_well: i32,
+        uranium_mine: i32,
+        barracks: i32,
+        farm: i32,
+        police_station: i32,
+        hospital: i32,
+        recycling_center: i32,
+        subway: i32,
+        supermarket: i32,
+        bank: i32,
+        shopping_mall: i32,
+        stadium: i32,
+        lead_mine: i32,
+        iron_mine: i32,
+        bauxite_mine: i32,
+        oil_refinery: i32,
+        aluminum_refinery: i32,
+        steel_mill: i32,
+        munitions_factory: i32,
+        factory: i32,
+        hangar: i32,
+        drydock: i32,
+        nuke_date: String,
+    }
+
+    EmbargoPayload, Embargo {
+        id: i32,
+        date: String,
+        sender_id: i32,
+        receiver_id: i32,
+        reason: String,
+        r#type: String,
+    }
+
+    NationPayload, Nation {
+        id: i32,
+        alliance_id: i32,
+        alliance_position: String,
+        alliance_position_id: i32,
+        nation_name: String,
+        leader_name: String,
+        continent: String,
+        war_policy: String,
+        war_policy_turns: i32,
+        domestic_policy: String,
+        domestic_policy_turns: i32,
+        color: String,
+        num_cities: i32,
+        score: f64,
+        update_tz: f64,
+        population: i64,
+        flag: String,
+        vacation_mode_turns: i32,
+        beige_turns: i32,
+        espionage_available: bool,
+        last_active: String,
+        date: String,
+        soldiers: i32,
+        tanks: i32,
+        aircraft: i32,
+        ships: i32,
+        missiles: i32,
+        nukes: i32,
+        spies: i32,
+        discord: String,
+        discord_id: String,
+        turns_since_last_city: i32,
+        turns_since_last_project: i32,
+        projects: i32,
+        project_bits: i64,
+        moderator_mode: i32,
+        wars_won: i32,
+        wars_lost: i32,
+        tax_id: i32,
+        alliance_seniority: i32,
+        gross_national_income: f64,
+        gross_domestic_product: f64,
+        vip: bool,
+        commendations: i32,
+        denouncements: i32,
+        offensive_wars_count: i32,
+        defensive_wars_count: i32,
+        money: f64,
+        coal: f64,
+        oil: f64,
+        uranium: f64,
+        iron: f64,
+        bauxite: f64,
+        lead: f64,
+        gasoline: f64,
+        munitions: f64,
+        steel: f64,
+        aluminum: f64,
+        food: f64,
+        credits: i32,
+    }
+
+    TaxBracketPayload, TaxBracket {
+        id: i32,
+        alliance_id: i32,
+        date: String,
+        date_modified: String,
+        last_modified_by_id: i32,
+        tax_rate: i32,
+        resource_tax_rate: i32,
+        bracket_name: String,
+    }
+
+    TradePayload, Trade {
+        id: i32,
+        r#type: String,
+        date: String,
+        sender_id: i32,
+        receiver_id: i32,
+        offer_resource: String,
+        offer_amount: i64,
+        buy_or_sell: String,
+        price: i64,
+        accepted: bool,
+        date_accepted: String,
+        original_trade_id: i32,
+    }
+
+    TreasureTradePayload, TreasureTrade {
+        id: i32,
+        offer_date: String,
+        accept_date: String,
+        sender_id: i32,
+        receiver_id: i32,
+        buying: bool,
+        selling: bool,
+        treasure: String,
+        money: i64,
+        accepted: bool,
+        rejected: bool,
+        seller_cancelled: bool,
+        buyer_cancelled: bool,
+    }
+
+    TreatyPayload, Treaty {
+        id: i32,
+        date: String,
+        treaty_type: String,
+        treaty_url: String,
+        turns_left: i32,
+        alliance1_id: i32,
+        alliance2_id: i32,
+        approved: bool,
+    }
+
+    WarAttackPayload, WarAttack {
+        id: i32,
+        date: String,
+        att_id: i32,
+        def_id: i32,
+        r#type: String,
+        war_id: i32,
+        victor: i32,
+        success: i32,
+        attcas1: i32,
+        attcas2: i32,
+        defcas1: i32,
+        defcas2: i32,
+        city_id: i32,
+        infra_destroyed: f64,
+        improvements_lost: i32,
+        money_stolen: f64,
+        loot_info: String,
+        resistance_eliminated: i32,
+        city_infra_before: f64,
+        infra_destroyed_value: f64,
+        att_mun_used: f64,
+        def_mun_used: f64,
+        att_gas_used: f64,
+        def_gas_used: f64,
+        aircraft_killed_by_tanks: i32,
+    }
+
+    WarPayload, War {
+        id: i32,
+        date: String,
+        reason: String,
+        war_type: String,
+        ground_control: i32,
+        air_superiority: i32,
+        naval_blockade: i32,
+        winner_id: i32,
+        turns_left: i32,
+        att_id: i32,
+        att_alliance_id: i32,
+        def_id: i32,
+        def_alliance_id: i32,
+        att_points: i32,
+        def_points: i32,
+        att_peace: bool,
+        def_peace: bool,
+        att_resistance: i32,
+        def_resistance: i32,
+        att_fortify: bool,
+        def_fortify: bool,
+        att_gas_used: f64,
+        def_gas_used: f64,
+        att_mun_used: f64,
+        def_mun_used: f64,
+        att_alum_used: i32,
+        def_alum_used: i32,
+        att_steel_used: i32,
+        def_steel_used: i32,
+        att_infra_destroyed: f64,
+        def_infra_destroyed: f64,
+        att_money_looted: f64,
+        def_money_looted: f64,
+        att_soldiers_lost: i32,
+        def_soldiers_lost: i32,
+        att_tanks_lost: i32,
+        def_tanks_lost: i32,
+        att_aircraft_lost: i32,
+        def_aircraft_lost: i32,
+        att_ships_lost: i32,
+        def_ships_lost: i32,
+        att_missiles_used: i32,
+        def_missiles_used: i32,
+        att_nukes_used: i32,
+        def_nukes_used: i32,
+        att_infra_destroyed_value: f64,
+        def_infra_destroyed_value: f64,
+    }
+
+    TradepricePayload, Tradeprice {
+        id: i32,
+        date: String,
+        coal: f64,
+        oil: f64,
+        uranium: f64,
+        iron: f64,
+        bauxite: f64,
+        lead: f64,
+        gasoline: f64,
+        munitions: f64,
+        steel: f64,
+        aluminum: f64,
+        food: f64,
+        credits: f64,
+    }
+);
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::{AlliancePayload, BountyPayload, NationPayload, SubscriptionPayload};
+    use crate::{
+        test_util::{block_on, subscription_kit},
+        Object, SubscriptionEvent, SubscriptionModel,
+    };
+
+    fn object(value: serde_json::Value) -> Object {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn fields_are_converted_leniently() {
+        let alliance = AlliancePayload::from_object(&object(json!({
+            "id": "12",
+            "name": "Rose",
+            "score": 1500,
+            "accept_members": 1,
+            "rank": 3.0,
+            "not_a_field": true,
+        })));
+        assert_eq!(alliance.id, Some(12));
+        assert_eq!(alliance.name.as_deref(), Some("Rose"));
+        assert_eq!(alliance.score, Some(1500.0));
+        assert_eq!(alliance.accept_members, Some(true));
+        assert_eq!(alliance.rank, Some(3));
+        // missing and mistyped fields are left out instead of failing
+        assert_eq!(alliance.acronym, None);
+        let alliance = AlliancePayload::from_object(&object(json!({ "id": "twelve" })));
+        assert_eq!(alliance.id, None);
+    }
+
+    #[test]
+    fn raw_identifiers_use_the_plain_name() {
+        let bounty = BountyPayload::from_object(&object(json!({
+            "id": 1,
+            "amount": "2500000",
+            "type": "NUCLEAR",
+        })));
+        assert_eq!(bounty.amount, Some(2500000));
+        assert_eq!(bounty.r#type.as_deref(), Some("NUCLEAR"));
+        assert_eq!(BountyPayload::model(), SubscriptionModel::Bounty);
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn payload_fields_list_every_field() {
+        let fields = super::payload_fields(&SubscriptionModel::Bounty);
+        assert_eq!(fields, vec!["id", "date", "nation_id", "amount", "type"]);
+    }
+
+    #[test]
+    fn typed_subscriptions_convert_messages() {
+        let (kit, _, socket) = subscription_kit();
+        block_on(async {
+            let subscription = kit
+                .subscribe_typed::<NationPayload>(SubscriptionEvent::Create)
+                .await
+                .unwrap();
+            socket
+                .deliver(
+                    "https://test/subscribe/nation/create",
+                    "NATION_CREATE",
+                    json!({ "id": 5, "nation_name": "Testland", "alliance_id": "0" }),
+                )
+                .await
+                .unwrap();
+            let message = subscription.next().await.unwrap();
+            assert_eq!(message.event, "NATION_CREATE");
+            assert_eq!(message.data.id, Some(5));
+            assert_eq!(message.data.nation_name.as_deref(), Some("Testland"));
+            assert_eq!(message.data.alliance_id, Some(0));
+        });
+    }
+
+    #[test]
+    fn typed_filters_must_match_the_payload_model() {
+        let (kit, _, _) = subscription_kit();
+        let err = block_on(kit.subscribe_typed_with_filter::<NationPayload>(
+            SubscriptionEvent::Update,
+            crate::SubscriptionFilter::new(SubscriptionModel::City).set_id([1]),
+        ))
+        .unwrap_err();
+        assert_eq!(err, "a city filter can't be used for nation payloads");
+    }
+}
@@ -0,0 +1,653 @@
+use std::time::{Duration, Instant};
+
+use serde_json::json;
+
+use crate::{Object, Value};
+
+// what the transport has to do after feeding the protocol a frame or a tick
+#[derive(Clone, Debug)]
+pub enum ProtocolAction {
+    Send(String),
+    Established(String),
+    SubscriptionSucceeded(String),
+    SubscriptionError {
+        channel: String,
+        reason: String,
+    },
+    // a `pusher:error` that didn't come with a close
+    Error {
+        code: Option<u16>,
+        message: String,
+    },
+    // `BULK_` events carry several payloads, the event is named without the
+    // prefix and each payload becomes a message of its own
+    Message {
+        event: String,
+        channel: String,
+        bulk: bool,
+        data: Vec<Object>,
+    },
+    // `immediate` is false when the server asked to back off first
+    Reconnect {
+        immediate: bool,
+    },
+    Fail(String),
+}
+
+// the Pusher protocol without any IO, the transport feeds it the frames it
+// receives and the current time and carries out the actions it returns
+#[derive(Clone, Debug)]
+pub struct PusherProtocol {
+    socket_id: Option<String>,
+    activity_timeout: Duration,
+    // the server's activity timeout is only used if it's lower
+    max_activity_timeout: Duration,
+    pong_timeout: Duration,
+    last_message: Option<Instant>,
+    ping_sent: Option<Instant>,
+    // the round trip of the last ping that was answered
+    ping_rtt: Option<Duration>,
+}
+
+impl PusherProtocol {
+    pub fn new() -> Self {
+        Self {
+            socket_id: None,
+            activity_timeout: Duration::from_secs(120),
+            max_activity_timeout: Duration::from_secs(120),
+            pong_timeout: Duration::from_secs(30),
+            last_message: None,
+            ping_sent: None,
+            ping_rtt: None,
+        }
+    }
+
+    pub fn set_activity_timeout(mut self, activity_timeout: Duration) -> Self {
+        self.activity_timeout = activity_timeout;
+        self.max_activity_timeout = activity_timeout;
+        self
+    }
+
+    pub fn set_pong_timeout(mut self, pong_timeout: Duration) -> Self {
+        self.pong_timeout = pong_timeout;
+        self
+    }
+
+    pub fn socket_id(&self) -> Option<&str> {
+        self.socket_id.as_deref()
+    }
+
+    pub fn is_established(&self) -> bool {
+        self.socket_id.is_some()
+    }
+
+    pub fn activity_timeout(&self) -> Duration {
+        self.activity_timeout
+    }
+
+    pub fn last_message(&self) -> Option<Instant> {
+        self.last_message
+    }
+
+    pub fn ping_rtt(&self) -> Option<Duration> {
+        self.ping_rtt
+    }
+
+    // call when a new connection is opened
+    pub fn reset(&mut self) {
+        self.socket_id = None;
+        self.activity_timeout = self.max_activity_timeout;
+        self.last_message = None;
+        self.ping_sent = None;
+    }
+
+    pub fn subscribe_frame(channel: &str, auth: &str) -> String {
+        json!({
+            "event": "pusher:subscribe",
+            "data": {
+                "channel": channel,
+                "auth": auth,
+            }
+        })
+        .to_string()
+    }
+
+    pub fn unsubscribe_frame(channel: &str) -> String {
+        json!({
+            "event": "pusher:unsubscribe",
+            "data": {
+                "channel": channel,
+            }
+        })
+        .to_string()
+    }
+
+    pub fn handle_text(
+        &mut self,
+        text: &str,
+        now: Instant,
+    ) -> Result<Option<ProtocolAction>, String> {
+        // any message shows the connection is alive, not just pongs
+        self.last_message = Some(now);
+        let ws_event = serde_json::from_str::<Value>(text)
+            .map_err(|e| e.to_string())?
+            .as_object()
+            .ok_or("malformed message")?;
+        let event = ws_event
+            .get("event")
+            .and_then(|e| e.value().as_string())
+            .ok_or("message without an event")?;
+        match event.as_str() {
+            "pusher:connection_established" => {
+                let data = ws_event
+                    .get("data")
+                    .and_then(|d| d.value().parse_object())
+                    .ok_or("malformed connection_established data")?;
+                let socket_id = data
+                    .get("socket_id")
+                    .and_then(|v| v.value().as_string())
+                    .ok_or("connection_established without a socket_id")?;
+                let activity_timeout = data
+                    .get("activity_timeout")
+                    .and_then(|v| v.value().as_u16())
+                    .ok_or("connection_established without an activity_timeout")?;
+                self.activity_timeout = self
+                    .max_activity_timeout
+                    .min(Duration::from_secs(activity_timeout.into()));
+                self.socket_id = Some(socket_id.clone());
+                Ok(Some(ProtocolAction::Established(socket_id)))
+            },
+            "pusher_internal:subscription_succeeded" => {
+                let channel = ws_event
+                    .get("channel")
+                    .and_then(|c| c.value().as_string())
+                    .ok_or("subscription_succeeded without a channel")?;
+                Ok(Some(ProtocolAction::SubscriptionSucceeded(channel)))
+            },
+            "pusher_internal:subscription_error" => {
+                let channel = ws_event
+                    .get("channel")
+                    .and_then(|c| c.value().as_string())
+                    .ok_or("subscription_error without a channel")?;
+                let data = ws_event.get("data").and_then(|d| d.value().parse_object());
+                let field = |name: &str| {
+                    data.as_ref()
+                        .and_then(|d| d.get(name).map(|v| v.value().clone()))
+                };
+                let mut reason = field("error")
+                    .or_else(|| field("type"))
+                    .and_then(|v| v.as_string())
+                    .unwrap_or_else(|| "subscription rejected".to_string());
+                if let Some(status) = field("status").and_then(|v| v.as_u16()) {
+                    reason = format!("{} (status {})", reason, status);
+                }
+                Ok(Some(ProtocolAction::SubscriptionError { channel, reason }))
+            },
+            "pusher:error" => {
+                let data = ws_event.get("data").and_then(|d| d.value().parse_object());
+                let message = data
+                    .as_ref()
+                    .and_then(|d| d.get("message").and_then(|v| v.value().as_string()))
+                    .unwrap_or_else(|| "unknown error".to_string());
+                let code = data
+                    .as_ref()
+                    .and_then(|d| d.get("code").and_then(|v| v.value().as_u16()));
+                Ok(Some(ProtocolAction::Error { code, message }))
+            },
+            "pusher:pong" => {
+                // other frames may come in between, the ping is only
+                // answered by this
+                if let Some(ping_sent) = self.ping_sent.take() {
+                    self.ping_rtt = Some(now.saturating_duration_since(ping_sent));
+                }
+                Ok(None)
+            },
+            "pusher:ping" => Ok(Some(ProtocolAction::Send(
+                json!({"event": "pusher:pong", "data": {}}).to_string(),
+            ))),
+            _ => {
+                let data = ws_event
+                    .get("data")
+                    .and_then(|d| d.value().string_to_value())
+                    .ok_or(format!("malformed {} data", event))?;
+                let channel = ws_event
+                    .get("channel")
+                    .and_then(|c| c.value().as_string())
+                    .ok_or(format!("{} without a channel", event))?;
+                let (event, bulk) = match event.strip_prefix("BULK_") {
+                    Some(event) => (event.to_string(), true),
+                    None => (event, false),
+                };
+                let data = if bulk {
+                    data.as_array().and_then(|data| {
+                        data.iter()
+                            .map(|i| i.as_object())
+                            .collect::<Option<Vec<_>>>()
+                    })
+                } else {
+                    data.as_object().map(|data| vec![data])
+                }
+                .ok_or(format!("malformed {} payload", event))?;
+                Ok(Some(ProtocolAction::Message {
+                    event,
+                    channel,
+                    bulk,
+                    data,
+                }))
+            },
+        }
+    }
+
+    pub fn handle_close(&mut self, code: Option<u16>) -> ProtocolAction {
+        self.socket_id = None;
+        self.ping_sent = None;
+        match code {
+            Some(code) if (4000..4100).contains(&code) => {
+                ProtocolAction::Fail(format!("socket closed with code {}", code))
+            },
+            Some(code) if (4200..4300).contains(&code) => {
+                ProtocolAction::Reconnect { immediate: true }
+            },
+            // 4100-4199 asks to back off before reconnecting, which is also
+            // the safe choice for anything unexpected
+            _ => ProtocolAction::Reconnect { immediate: false },
+        }
+    }
+
+    // pings once the connection was quiet for the activity timeout and asks to
+    // reconnect if neither the pong nor anything else comes in time
+    pub fn poll(&mut self, now: Instant) -> Option<ProtocolAction> {
+        if !self.is_established() {
+            return None;
+        }
+        if let Some(ping_sent) = self.ping_sent {
+            if now.saturating_duration_since(ping_sent) >= self.pong_timeout {
+                self.ping_sent = None;
+                // the connection is alive, the pong was just lost
+                if matches!(self.last_message, Some(last_message) if last_message > ping_sent) {
+                    return None;
+                }
+                self.socket_id = None;
+                self.ping_sent = None;
+                return Some(ProtocolAction::Reconnect { immediate: true });
+            }
+            return None;
+        }
+        let last_message = *self.last_message.get_or_insert(now);
+        if now.saturating_duration_since(last_message) >= self.activity_timeout {
+            self.ping_sent = Some(now);
+            return Some(ProtocolAction::Send(
+                json!({"event": "pusher:ping", "data": {}}).to_string(),
+            ));
+        }
+        None
+    }
+
+    // how long until `poll` could have something to do
+    pub fn poll_in(&self, now: Instant) -> Duration {
+        match (self.ping_sent, self.last_message) {
+            (Some(ping_sent), _) => self
+                .pong_timeout
+                .saturating_sub(now.saturating_duration_since(ping_sent)),
+            (None, Some(last_message)) if self.is_established() => self
+                .activity_timeout
+                .saturating_sub(now.saturating_duration_since(last_message)),
+            (None, _) if self.is_established() => self.activity_timeout,
+            // nothing to do until the connection is established, which the
+            // transport will notice soon after
+            _ => Duration::from_secs(1),
+        }
+    }
+}
+
+impl Default for PusherProtocol {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use serde_json::json;
+
+    use super::{ProtocolAction, PusherProtocol};
+
+    fn frame(event: &str, channel: Option<&str>, data: serde_json::Value) -> String {
+        let mut frame = json!({ "event": event, "data": data.to_string() });
+        if let Some(channel) = channel {
+            frame["channel"] = channel.into();
+        }
+        frame.to_string()
+    }
+
+    fn established(activity_timeout: u64, now: Instant) -> PusherProtocol {
+        let mut protocol = PusherProtocol::new().set_pong_timeout(Duration::from_secs(10));
+        let action = protocol
+            .handle_text(
+                &frame(
+                    "pusher:connection_established",
+                    None,
+                    json!({ "socket_id": "1.1", "activity_timeout": activity_timeout }),
+                ),
+                now,
+            )
+            .unwrap();
+        assert!(matches!(action, Some(ProtocolAction::Established(id)) if id == "1.1"));
+        protocol
+    }
+
+    #[test]
+    fn connection_established_sets_the_activity_timeout() {
+        let now = Instant::now();
+        let protocol = established(30, now);
+        assert!(protocol.is_established());
+        assert_eq!(protocol.socket_id(), Some("1.1"));
+        assert_eq!(protocol.activity_timeout(), Duration::from_secs(30));
+        // the server can't make it longer than the configured one
+        let protocol = established(500, now);
+        assert_eq!(protocol.activity_timeout(), Duration::from_secs(120));
+
+        let mut protocol = PusherProtocol::new();
+        let err = protocol
+            .handle_text(
+                &frame(
+                    "pusher:connection_established",
+                    None,
+                    json!({ "activity_timeout": 30 }),
+                ),
+                now,
+            )
+            .unwrap_err();
+        assert_eq!(err, "connection_established without a socket_id");
+        assert!(!protocol.is_established());
+    }
+
+    #[test]
+    fn pings_are_answered() {
+        let now = Instant::now();
+        let mut protocol = established(120, now);
+        let action = protocol
+            .handle_text(&frame("pusher:ping", None, json!({})), now)
+            .unwrap();
+        match action {
+            Some(ProtocolAction::Send(pong)) => {
+                let pong = serde_json::from_str::<serde_json::Value>(&pong).unwrap();
+                assert_eq!(pong["event"], "pusher:pong");
+            },
+            action => panic!("expected a pong, got {:?}", action),
+        }
+    }
+
+    #[test]
+    fn subscription_replies() {
+        let now = Instant::now();
+        let mut protocol = established(120, now);
+        let action = protocol
+            .handle_text(
+                &frame(
+                    "pusher_internal:subscription_succeeded",
+                    Some("a"),
+                    json!({}),
+                ),
+                now,
+            )
+            .unwrap();
+        assert!(matches!(action, Some(ProtocolAction::SubscriptionSucceeded(c)) if c == "a"));
+
+        let action = protocol
+            .handle_text(
+                &frame(
+                    "pusher_internal:subscription_error",
+                    Some("b"),
+                    json!({ "type": "AuthError", "error": "bad auth", "status": 401 }),
+                ),
+                now,
+            )
+            .unwrap();
+        assert!(matches!(
+            action,
+            Some(ProtocolAction::SubscriptionError { channel, reason })
+                if channel == "b" && reason == "bad auth (status 401)"
+        ));
+
+        let action = protocol
+            .handle_text(
+                &frame("pusher_internal:subscription_error", Some("c"), json!({})),
+                now,
+            )
+            .unwrap();
+        assert!(matches!(
+            action,
+            Some(ProtocolAction::SubscriptionError { reason, .. }) if reason == "subscription rejected"
+        ));
+    }
+
+    #[test]
+    fn errors_are_reported() {
+        let now = Instant::now();
+        let mut protocol = established(120, now);
+        let action = protocol
+            .handle_text(
+                &frame(
+                    "pusher:error",
+                    None,
+                    json!({ "code": 4301, "message": "over quota" }),
+                ),
+                now,
+            )
+            .unwrap();
+        assert!(matches!(
+            action,
+            Some(ProtocolAction::Error { code: Some(4301), message }) if message == "over quota"
+        ));
+        assert!(protocol.is_established());
+    }
+
+    #[test]
+    fn bulk_events_are_split() {
+        let now = Instant::now();
+        let mut protocol = established(120, now);
+        let action = protocol
+            .handle_text(
+                &frame(
+                    "BULK_NATION_UPDATE",
+                    Some("a"),
+                    json!([{ "id": 1 }, { "id": 2 }]),
+                ),
+                now,
+            )
+            .unwrap();
+        match action {
+            Some(ProtocolAction::Message {
+                event,
+                channel,
+                bulk,
+                data,
+            }) => {
+                assert_eq!(event, "NATION_UPDATE");
+                assert_eq!(channel, "a");
+                assert!(bulk);
+                let ids = data
+                    .iter()
+                    .map(|d| d.get("id").unwrap().as_i64().unwrap())
+                    .collect::<Vec<_>>();
+                assert_eq!(ids, vec![1, 2]);
+            },
+            action => panic!("expected a message, got {:?}", action),
+        }
+
+        let action = protocol
+            .handle_text(&frame("NATION_UPDATE", Some("a"), json!({ "id": 3 })), now)
+            .unwrap();
+        assert!(matches!(
+            action,
+            Some(ProtocolAction::Message { bulk: false, data, .. }) if data.len() == 1
+        ));
+    }
+
+    #[test]
+    fn malformed_events_are_rejected() {
+        let now = Instant::now();
+        let mut protocol = established(120, now);
+        for (event, data) in [
+            ("NATION_UPDATE", json!([1])),
+            ("BULK_NATION_UPDATE", json!({ "id": 1 })),
+            ("BULK_NATION_UPDATE", json!([1])),
+        ] {
+            let err = protocol
+                .handle_text(&frame(event, Some("a"), data), now)
+                .unwrap_err();
+            assert_eq!(err, "malformed NATION_UPDATE payload");
+        }
+        let err = protocol
+            .handle_text(&frame("NATION_UPDATE", None, json!({})), now)
+            .unwrap_err();
+        assert_eq!(err, "NATION_UPDATE without a channel");
+        assert_eq!(
+            protocol.handle_text("{}", now).unwrap_err(),
+            "message without an event"
+        );
+        assert!(protocol.handle_text("not json", now).is_err());
+    }
+
+    #[test]
+    fn quiet_connections_are_pinged() {
+        let start = Instant::now();
+        let mut protocol = established(30, start);
+        assert_eq!(protocol.poll_in(start), Duration::from_secs(30));
+        assert!(protocol.poll(start + Duration::from_secs(29)).is_none());
+        // any message resets the timer
+        protocol
+            .handle_text(
+                &frame("NATION_UPDATE", Some("a"), json!({ "id": 1 })),
+                start + Duration::from_secs(20),
+            )
+            .unwrap();
+        assert!(protocol.poll(start + Duration::from_secs(30)).is_none());
+
+        let ping_at = start + Duration::from_secs(50);
+        match protocol.poll(ping_at) {
+            Some(ProtocolAction::Send(ping)) => {
+                let ping = serde_json::from_str::<serde_json::Value>(&ping).unwrap();
+                assert_eq!(ping["event"], "pusher:ping");
+            },
+            action => panic!("expected a ping, got {:?}", action),
+        }
+        // waiting for the pong now
+        assert_eq!(protocol.poll_in(ping_at), Duration::from_secs(10));
+        assert!(protocol.poll(ping_at + Duration::from_secs(5)).is_none());
+        protocol
+            .handle_text(
+                &frame("pusher:pong", None, json!({})),
+                ping_at + Duration::from_secs(2),
+            )
+            .unwrap();
+        assert_eq!(protocol.ping_rtt(), Some(Duration::from_secs(2)));
+        assert!(protocol.poll(ping_at + Duration::from_secs(20)).is_none());
+    }
+
+    #[test]
+    fn pings_are_timed_across_other_frames() {
+        let start = Instant::now();
+        let mut protocol = established(30, start);
+        let ping_at = start + Duration::from_secs(30);
+        assert!(matches!(
+            protocol.poll(ping_at),
+            Some(ProtocolAction::Send(_))
+        ));
+        protocol
+            .handle_text(
+                &frame("NATION_UPDATE", Some("a"), json!({ "id": 1 })),
+                ping_at + Duration::from_secs(1),
+            )
+            .unwrap();
+        assert_eq!(protocol.ping_rtt(), None);
+        protocol
+            .handle_text(
+                &frame("pusher:pong", None, json!({})),
+                ping_at + Duration::from_secs(3),
+            )
+            .unwrap();
+        assert_eq!(protocol.ping_rtt(), Some(Duration::from_secs(3)));
+        // a pong nobody asked for changes nothing
+        protocol
+            .handle_text(
+                &frame("pusher:pong", None, json!({})),
+                ping_at + Duration::from_secs(4),
+            )
+            .unwrap();
+        assert_eq!(protocol.ping_rtt(), Some(Duration::from_secs(3)));
+    }
+
+    #[test]
+    fn lost_pongs_on_a_busy_connection_dont_reconnect() {
+        let start = Instant::now();
+        let mut protocol = established(30, start);
+        let ping_at = start + Duration::from_secs(30);
+        assert!(matches!(
+            protocol.poll(ping_at),
+            Some(ProtocolAction::Send(_))
+        ));
+        let message_at = ping_at + Duration::from_secs(5);
+        protocol
+            .handle_text(
+                &frame("NATION_UPDATE", Some("a"), json!({ "id": 1 })),
+                message_at,
+            )
+            .unwrap();
+        assert!(protocol.poll(ping_at + Duration::from_secs(10)).is_none());
+        assert!(protocol.is_established());
+        assert_eq!(protocol.ping_rtt(), None);
+        // pinged again once it's quiet for the activity timeout
+        assert_eq!(
+            protocol.poll_in(ping_at + Duration::from_secs(10)),
+            Duration::from_secs(25)
+        );
+        assert!(matches!(
+            protocol.poll(message_at + Duration::from_secs(30)),
+            Some(ProtocolAction::Send(_))
+        ));
+    }
+
+    #[test]
+    fn missing_pongs_reconnect() {
+        let start = Instant::now();
+        let mut protocol = established(30, start);
+        let ping_at = start + Duration::from_secs(30);
+        assert!(matches!(
+            protocol.poll(ping_at),
+            Some(ProtocolAction::Send(_))
+        ));
+        assert!(protocol.poll(ping_at + Duration::from_secs(9)).is_none());
+        assert!(matches!(
+            protocol.poll(ping_at + Duration::from_secs(10)),
+            Some(ProtocolAction::Reconnect { immediate: true })
+        ));
+        assert!(!protocol.is_established());
+        assert!(protocol.poll(ping_at + Duration::from_secs(60)).is_none());
+    }
+
+    #[test]
+    fn close_codes() {
+        let mut protocol = established(120, Instant::now());
+        assert!(matches!(
+            protocol.handle_close(Some(4001)),
+            ProtocolAction::Fail(reason) if reason == "socket closed with code 4001"
+        ));
+        assert!(!protocol.is_established());
+        assert!(matches!(
+            protocol.handle_close(Some(4201)),
+            ProtocolAction::Reconnect { immediate: true }
+        ));
+        assert!(matches!(
+            protocol.handle_close(Some(4100)),
+            ProtocolAction::Reconnect { immediate: false }
+        ));
+        assert!(matches!(
+            protocol.handle_close(None),
+            ProtocolAction::Reconnect { immediate: false }
+        ));
+    }
+}
@@ -1,12 +1,14 @@
+use serde::{Deserialize, Serialize};
+
 use crate::{Field, Variable};
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub enum QueryType {
     Mutation,
     Query,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub struct Query {
     pub(crate) query_type: QueryType,
     pub(crate) fields: Vec<Field>,
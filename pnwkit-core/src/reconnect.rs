@@ -0,0 +1,149 @@
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    time::Duration,
+};
+
+#[derive(Clone, Debug)]
+pub struct ReconnectPolicy {
+    pub initial_delay: Duration,
+    pub multiplier: f64,
+    pub max_delay: Duration,
+    // the fraction of each delay that is randomized, 0.0 to 1.0
+    pub jitter: f64,
+    // `None` keeps trying forever
+    pub max_attempts: Option<u32>,
+}
+
+impl ReconnectPolicy {
+    pub fn new() -> Self {
+        Self {
+            initial_delay: Duration::from_secs(1),
+            multiplier: 2.0,
+            max_delay: Duration::from_secs(60),
+            jitter: 0.1,
+            max_attempts: Some(10),
+        }
+    }
+
+    pub fn set_initial_delay(mut self, initial_delay: Duration) -> Self {
+        self.initial_delay = initial_delay;
+        self
+    }
+
+    pub fn set_multiplier(mut self, multiplier: f64) -> Self {
+        self.multiplier = multiplier;
+        self
+    }
+
+    pub fn set_max_delay(mut self, max_delay: Duration) -> Self {
+        self.max_delay = max_delay;
+        self
+    }
+
+    pub fn set_jitter(mut self, jitter: f64) -> Self {
+        self.jitter = jitter.clamp(0.0, 1.0);
+        self
+    }
+
+    pub fn set_max_attempts(mut self, max_attempts: Option<u32>) -> Self {
+        self.max_attempts = max_attempts;
+        self
+    }
+
+    pub fn exhausted(&self, attempts: u32) -> bool {
+        matches!(self.max_attempts, Some(max_attempts) if attempts >= max_attempts)
+    }
+
+    // the delay before the given attempt, starting from zero
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let delay = self.initial_delay.as_secs_f64() * self.multiplier.powi(attempt as i32);
+        let delay = delay.min(self.max_delay.as_secs_f64());
+        // a fresh `RandomState` is randomly seeded, which is plenty for jitter
+        // and saves pulling in a dependency for it
+        let random = RandomState::new().build_hasher().finish() as f64 / u64::MAX as f64;
+        // the field can be set without `set_jitter`
+        let delay = delay * (1.0 - self.jitter.clamp(0.0, 1.0) * random);
+        // a max delay near `Duration::MAX` doesn't survive the round trip
+        // through f64
+        Duration::try_from_secs_f64(delay.max(0.0)).unwrap_or(self.max_delay)
+    }
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ReconnectPolicy;
+    use std::time::Duration;
+
+    #[test]
+    fn delays_back_off_up_to_the_max() {
+        let policy = ReconnectPolicy::new()
+            .set_jitter(0.0)
+            .set_max_delay(Duration::from_secs(10));
+
+        let delays = (0..6)
+            .map(|i| policy.delay(i).as_secs())
+            .collect::<Vec<_>>();
+        assert_eq!(delays, vec![1, 2, 4, 8, 10, 10]);
+        assert_eq!(policy.delay(1000), Duration::from_secs(10));
+    }
+
+    #[test]
+    fn jitter_only_shortens_delays() {
+        let policy = ReconnectPolicy::new()
+            .set_initial_delay(Duration::from_secs(4))
+            .set_multiplier(1.0)
+            .set_jitter(0.5);
+
+        let delays = (0..200).map(|i| policy.delay(i)).collect::<Vec<_>>();
+        for delay in &delays {
+            assert!(*delay >= Duration::from_secs(2) && *delay <= Duration::from_secs(4));
+        }
+        // the delays are actually randomized
+        assert!(delays.iter().any(|delay| *delay != delays[0]));
+    }
+
+    #[test]
+    fn jitter_is_clamped() {
+        assert_eq!(ReconnectPolicy::new().set_jitter(3.0).jitter, 1.0);
+        assert_eq!(ReconnectPolicy::new().set_jitter(-1.0).jitter, 0.0);
+
+        let mut policy = ReconnectPolicy::new().set_jitter(7.0);
+        for i in 0..50 {
+            assert!(policy.delay(i) <= policy.max_delay);
+        }
+        policy.jitter = 7.0;
+        for i in 0..50 {
+            assert!(policy.delay(i) <= policy.max_delay);
+        }
+        policy.jitter = -7.0;
+        assert_eq!(policy.delay(50), policy.max_delay);
+    }
+
+    #[test]
+    fn huge_delays_dont_overflow() {
+        let policy = ReconnectPolicy::new()
+            .set_jitter(0.0)
+            .set_multiplier(f64::MAX)
+            .set_max_delay(Duration::MAX);
+        assert_eq!(policy.delay(0), Duration::from_secs(1));
+        assert_eq!(policy.delay(1000), Duration::MAX);
+    }
+
+    #[test]
+    fn attempts_are_limited() {
+        let policy = ReconnectPolicy::new().set_max_attempts(Some(3));
+        assert!(!policy.exhausted(2));
+        assert!(policy.exhausted(3));
+        assert!(policy.exhausted(4));
+
+        let policy = policy.set_max_attempts(None);
+        assert!(!policy.exhausted(u32::MAX));
+    }
+}
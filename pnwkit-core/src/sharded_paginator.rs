@@ -0,0 +1,327 @@
+use std::{
+    collections::{HashSet, VecDeque},
+    marker::PhantomData,
+};
+
+use futures_util::future::join_all;
+use serde::de::DeserializeOwned;
+
+use crate::{
+    paginator::{deserialize_item, PaginatorItemError},
+    query::{Query, QueryType},
+    Field, Kit, Paginator, Value,
+};
+
+// records only move a page or so while paging, so only the most recent ids
+// are remembered
+const SEEN_CAPACITY: usize = 16384;
+
+// splits a collection into `min_id`/`max_id` ranges with a paginator each, so
+// deep pages are avoided and the shards can be fetched concurrently, the rate
+// limiter is shared through the kit so the shards don't need to coordinate
+#[derive(Debug)]
+pub struct ShardedPaginator<T = Value> {
+    shards: Vec<Paginator<Value>>,
+    // records can move between pages while paging, so any id that was
+    // returned recently is skipped
+    seen: HashSet<i64>,
+    seen_order: VecDeque<i64>,
+    // the shards that failed in the last fill and why
+    errors: Vec<(usize, String)>,
+    // items are taken from each shard in turn so they run out together and
+    // are refilled in the same fill
+    next_shard: usize,
+    _item: PhantomData<T>,
+}
+
+impl<T: DeserializeOwned + 'static> ShardedPaginator<T> {
+    pub fn new(field: Field, min_id: i64, max_id: i64, shards: u32) -> Self {
+        // the bounds can be anywhere in the range of an i64, so the sizes are
+        // worked out in an i128 where they can't overflow
+        let (min_id, max_id) = (min_id as i128, max_id as i128);
+        let shards = shards.max(1) as i128;
+        let size = ((max_id - min_id + 1 + shards - 1) / shards).max(1);
+        let mut paginators = Vec::with_capacity(shards as usize);
+        let mut lower = min_id;
+        while lower <= max_id {
+            let upper = (lower + size - 1).min(max_id);
+            let field = field
+                .clone()
+                .set_argument("min_id".into(), (lower as i64).into())
+                .set_argument("max_id".into(), (upper as i64).into())
+                .will_paginate();
+            paginators.push(Paginator::new(Query::new(QueryType::Query).field(field)));
+            lower = upper + 1;
+        }
+        Self {
+            shards: paginators,
+            seen: HashSet::new(),
+            seen_order: VecDeque::new(),
+            errors: Vec::new(),
+            next_shard: 0,
+            _item: PhantomData,
+        }
+    }
+
+    pub fn set_per_page(mut self, per_page: u16) -> Self {
+        self.shards = self
+            .shards
+            .into_iter()
+            .map(|s| s.set_per_page(per_page))
+            .collect();
+        self
+    }
+
+    pub fn shards(&self) -> &[Paginator<Value>] {
+        &self.shards
+    }
+
+    pub fn errors(&self) -> &[(usize, String)] {
+        &self.errors
+    }
+
+    // items from the shards that could be fetched are returned even if others
+    // failed, `None` with `errors` set means nothing else could be fetched,
+    // calling it again retries the shards that failed
+    pub async fn next(&mut self, kit: &Kit) -> Option<Result<T, PaginatorItemError>> {
+        loop {
+            let shards = self.shards.len();
+            let popped = (0..shards).find_map(|offset| {
+                let i = (self.next_shard + offset) % shards;
+                self.shards[i].pop_raw().map(|item| (i, item))
+            });
+            let (page, index, value) = match popped {
+                Some((i, item)) => {
+                    self.next_shard = (i + 1) % shards;
+                    item
+                },
+                None => {
+                    if self.shards.iter().all(|s| s.finished()) {
+                        return None;
+                    }
+                    if self.fill(kit).await.is_err() && self.shards.iter().all(|s| s.is_empty()) {
+                        return None;
+                    }
+                    continue;
+                },
+            };
+            let id = value
+                .as_object()
+                .and_then(|o| o.get("id").and_then(|id| id.value().as_i64()));
+            if let Some(id) = id {
+                if !self.remember(id) {
+                    continue;
+                }
+            }
+            return Some(deserialize_item(page, index, value));
+        }
+    }
+
+    // returns false if the id was returned recently
+    fn remember(&mut self, id: i64) -> bool {
+        if !self.seen.insert(id) {
+            return false;
+        }
+        self.seen_order.push_back(id);
+        if self.seen_order.len() > SEEN_CAPACITY {
+            if let Some(id) = self.seen_order.pop_front() {
+                self.seen.remove(&id);
+            }
+        }
+        true
+    }
+
+    // fetches the next page of every shard that ran out of items, the shards
+    // that failed are kept in `errors`
+    pub async fn fill(&mut self, kit: &Kit) -> Result<(), String> {
+        let results = join_all(
+            self.shards
+                .iter_mut()
+                .enumerate()
+                .filter(|(_, s)| s.is_empty() && !s.finished())
+                .map(|(i, s)| async move { (i, s.fill(kit).await) }),
+        )
+        .await;
+        self.errors = results
+            .into_iter()
+            .filter_map(|(i, res)| res.err().map(|e| (i, e)))
+            .collect();
+        match self.errors.as_slice() {
+            [] => Ok(()),
+            errors => Err(errors
+                .iter()
+                .map(|(i, e)| format!("shard {}: {}", i, e))
+                .collect::<Vec<_>>()
+                .join(", ")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use serde_json::json;
+
+    use crate::{
+        field,
+        test_util::{
+            block_on, body, counting_page, id, kit, page, page_number, variable, MockClient,
+        },
+        Kit, ShardedPaginator,
+    };
+
+    fn ranges(client: &MockClient) -> Vec<String> {
+        let mut ranges = client
+            .bodies()
+            .iter()
+            .map(|b| range(b["query"].as_str().unwrap()))
+            .collect::<Vec<_>>();
+        ranges.sort();
+        ranges.dedup();
+        ranges
+    }
+
+    fn range(query: &str) -> String {
+        let bound = |name: &str| {
+            let start = query.find(name).unwrap() + name.len() + 2;
+            let end = query[start..].find([',', ')']).unwrap() + start;
+            query[start..end].to_string()
+        };
+        format!("{}..{}", bound("min_id"), bound("max_id"))
+    }
+
+    fn collect(paginator: &mut ShardedPaginator, kit: &Kit) -> Vec<i64> {
+        block_on(async {
+            let mut ids = Vec::new();
+            while let Some(item) = paginator.next(kit).await {
+                ids.push(id(&item.unwrap()));
+            }
+            ids
+        })
+    }
+
+    #[test]
+    fn splits_the_range_into_shards() {
+        let client = MockClient::new(|_| page(vec![], 1, false));
+        let kit = kit(client.clone());
+        let mut paginator = kit.sharded_paginator(field("nations"), 1, 10, 3);
+        assert_eq!(paginator.shards().len(), 3);
+        assert!(collect(&mut paginator, &kit).is_empty());
+        assert_eq!(ranges(&client), vec!["1..4", "5..8", "9..10"]);
+    }
+
+    #[test]
+    fn extreme_ranges_do_not_overflow() {
+        let client = MockClient::new(|_| page(vec![], 1, false));
+        let kit = kit(client.clone());
+        let paginator = kit.sharded_paginator(field("nations"), i64::MIN, i64::MAX, 2);
+        assert_eq!(paginator.shards().len(), 2);
+        let paginator = kit.sharded_paginator(field("nations"), i64::MAX - 1, i64::MAX, 4);
+        assert_eq!(paginator.shards().len(), 2);
+        let mut paginator = kit.sharded_paginator(field("nations"), 5, 1, 2);
+        assert!(paginator.shards().is_empty());
+        assert!(collect(&mut paginator, &kit).is_empty());
+        assert!(client.requests().is_empty());
+    }
+
+    #[test]
+    fn a_failed_shard_keeps_the_others_and_is_retried() {
+        // the kit retries a request four times before giving up
+        let failures = Arc::new(AtomicUsize::new(4));
+        let client = MockClient::new({
+            let failures = failures.clone();
+            move |request| {
+                let body = body(request);
+                let lower = if range(body["query"].as_str().unwrap()) == "1..5" {
+                    1
+                } else {
+                    if failures
+                        .fetch_update(Ordering::AcqRel, Ordering::Acquire, |f| f.checked_sub(1))
+                        .is_ok()
+                    {
+                        return Err("connection reset".into());
+                    }
+                    6
+                };
+                counting_page(lower, page_number(&body), 2)
+            }
+        });
+        let kit = kit(client.clone());
+        let mut paginator = kit.sharded_paginator(field("nations"), 1, 10, 2);
+
+        let first = block_on(async {
+            let mut ids = Vec::new();
+            for _ in 0..2 {
+                ids.push(id(&paginator.next(&kit).await.unwrap().unwrap()));
+            }
+            ids
+        });
+        assert_eq!(first, vec![1, 2]);
+        assert_eq!(paginator.errors().len(), 1);
+        assert_eq!(paginator.errors()[0].0, 1);
+
+        let mut ids = collect(&mut paginator, &kit);
+        ids.sort();
+        assert_eq!(ids, vec![3, 4, 6, 7, 8, 9]);
+        assert!(paginator.errors().is_empty());
+        // the failed page was requested again instead of being skipped
+        let pages = client
+            .bodies()
+            .iter()
+            .filter(|b| range(b["query"].as_str().unwrap()) == "6..10")
+            .map(|b| variable(b, "__page"))
+            .collect::<Vec<_>>();
+        assert_eq!(pages.len(), 6);
+        assert!(pages[..5].iter().all(|p| p == &json!(1)));
+        assert_eq!(pages[5], json!(2));
+    }
+
+    #[test]
+    fn shards_are_read_in_turn() {
+        let client = MockClient::new(|request| {
+            let body = body(request);
+            let lower = if range(body["query"].as_str().unwrap()) == "1..5" {
+                1
+            } else {
+                6
+            };
+            counting_page(lower, page_number(&body), 2)
+        });
+        let kit = kit(client.clone());
+        let mut paginator = kit.sharded_paginator(field("nations"), 1, 10, 2);
+        assert_eq!(collect(&mut paginator, &kit), vec![1, 6, 2, 7, 3, 8, 4, 9]);
+        // both shards ran out at once so their second pages were fetched in
+        // the same fill
+        assert_eq!(client.requests().len(), 4);
+    }
+
+    #[test]
+    fn a_shard_that_keeps_failing_ends_with_errors() {
+        let kit = kit(MockClient::new(|_| Err("down".into())));
+        let mut paginator = kit.sharded_paginator(field("nations"), 1, 10, 2);
+        assert!(collect(&mut paginator, &kit).is_empty());
+        assert_eq!(paginator.errors().len(), 2);
+        assert!(paginator.errors()[0].1.contains("down"));
+    }
+
+    #[test]
+    fn records_seen_on_an_earlier_page_are_skipped() {
+        let kit = kit(MockClient::new(|request| {
+            let page_number = page_number(&body(request));
+            // a record was deleted so the second page starts with the last
+            // one of the first
+            let ids = if page_number == 1 { [1, 2] } else { [2, 3] };
+            page(
+                ids.iter().map(|id| json!({ "id": id })).collect(),
+                page_number,
+                page_number < 2,
+            )
+        }));
+        let mut paginator = kit.sharded_paginator(field("nations"), 1, 10, 1);
+        assert_eq!(collect(&mut paginator, &kit), vec![1, 2, 3]);
+    }
+}
@@ -1,10 +1,285 @@
-use std::{fmt::Debug, sync::Arc};
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt::{Debug, Display},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
 use async_trait::async_trait;
+use tokio::sync::{broadcast, oneshot};
 
-use crate::{event::Event, Kit, Subscription};
+use crate::{event::Event, Kit, SubscriptionModel, SubscriptionState};
 
-// subscriptions: Arc<RwLock<DashMap<String, Arc<Subscription>>>>
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ConnectionState {
+    Disconnected,
+    Connecting,
+    Established,
+    Reconnecting,
+    Failed(String),
+}
+
+impl ConnectionState {
+    // how far the state is from established, for combining several
+    fn severity(&self) -> u8 {
+        match self {
+            Self::Established => 0,
+            Self::Connecting => 1,
+            Self::Disconnected => 2,
+            Self::Reconnecting => 3,
+            Self::Failed(_) => 4,
+        }
+    }
+
+    // the least healthy of several connections' states, disconnected if there
+    // are none
+    pub fn combine(states: impl IntoIterator<Item = ConnectionState>) -> ConnectionState {
+        states
+            .into_iter()
+            .max_by_key(|state| state.severity())
+            .unwrap_or(ConnectionState::Disconnected)
+    }
+}
+
+// a snapshot of a connection's health
+#[derive(Clone, Debug)]
+pub struct SocketStatus {
+    pub state: ConnectionState,
+    pub socket_id: Option<String>,
+    // the round trip of the last ping that was answered, pings are only sent
+    // once the connection has been quiet for the activity timeout
+    pub ping_rtt: Option<Duration>,
+    pub since_last_message: Option<Duration>,
+    pub activity_timeout: Duration,
+    // reconnects that succeeded since the socket was created
+    pub reconnects: u64,
+    // Pusher events received on each channel that is routed, a `BULK_` event
+    // counts once
+    pub channel_messages: HashMap<String, u64>,
+}
+
+impl SocketStatus {
+    // the status of several connections as one, the state is the least
+    // healthy one and the timings are the worst of them
+    pub fn combine(statuses: Vec<SocketStatus>) -> SocketStatus {
+        let socket_id = match statuses.as_slice() {
+            [status] => status.socket_id.clone(),
+            _ => None,
+        };
+        let mut combined = SocketStatus {
+            state: ConnectionState::Disconnected,
+            socket_id,
+            ping_rtt: None,
+            since_last_message: None,
+            activity_timeout: Duration::MAX,
+            reconnects: 0,
+            channel_messages: HashMap::new(),
+        };
+        for (i, status) in statuses.into_iter().enumerate() {
+            if i == 0 || status.state.severity() > combined.state.severity() {
+                combined.state = status.state;
+            }
+            combined.ping_rtt = combined.ping_rtt.max(status.ping_rtt);
+            combined.since_last_message =
+                combined.since_last_message.max(status.since_last_message);
+            combined.activity_timeout = combined.activity_timeout.min(status.activity_timeout);
+            combined.reconnects += status.reconnects;
+            combined.channel_messages.extend(status.channel_messages);
+        }
+        if combined.activity_timeout == Duration::MAX {
+            combined.activity_timeout = Duration::ZERO;
+        }
+        combined
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum ConnectionEvent {
+    Connecting,
+    Established(String),
+    Closed(Option<u16>),
+    Reconnecting,
+    // something went wrong that the socket recovered from, like a malformed
+    // message
+    Error(String),
+    // a subscription couldn't be resubscribed after a reconnect and was
+    // closed, the others are unaffected
+    SubscriptionFailed { channel: String, reason: String },
+    // the socket gave up and won't reconnect on its own, every subscription
+    // is closed when this is sent
+    Failed(String),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SendError {
+    // the socket gave up on the connection before the frame could be sent
+    Abandoned(String),
+    Transport(String),
+}
+
+impl Display for SendError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Abandoned(reason) => write!(f, "send abandoned: {}", reason),
+            Self::Transport(err) => write!(f, "send failed: {}", err),
+        }
+    }
+}
+
+impl From<SendError> for String {
+    fn from(err: SendError) -> Self {
+        err.to_string()
+    }
+}
+
+type QueuedFrame = (String, oneshot::Sender<Result<(), SendError>>);
+
+// frames sent while the socket isn't established wait here until it is, the
+// sender is told how it went once the frame was written or abandoned
+#[derive(Debug, Default)]
+pub struct OutgoingQueue {
+    frames: Mutex<VecDeque<QueuedFrame>>,
+}
+
+impl OutgoingQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&self, frame: String) -> oneshot::Receiver<Result<(), SendError>> {
+        let (sender, receiver) = oneshot::channel();
+        self.frames.lock().unwrap().push_back((frame, sender));
+        receiver
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.lock().unwrap().is_empty()
+    }
+
+    // the frames in the order they were sent, for flushing
+    pub fn take(&self) -> Vec<QueuedFrame> {
+        self.frames.lock().unwrap().drain(..).collect()
+    }
+
+    pub fn abandon(&self, reason: &str) {
+        for (_, sender) in self.take() {
+            let _ = sender.send(Err(SendError::Abandoned(reason.into())));
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SocketAssignment {
+    // a connection per subscription model
+    ByModel,
+    // fills each connection up to the given number of channels
+    MaxChannels(usize),
+}
+
+struct PooledSocket {
+    socket: Arc<dyn Socket>,
+    model: Option<SubscriptionModel>,
+    channels: usize,
+}
+
+// spreads subscriptions over several connections, which each reconnect on
+// their own, connections are opened by `new_socket` as they're needed
+pub struct SocketPool {
+    assignment: SocketAssignment,
+    new_socket: Box<dyn Fn() -> Box<dyn Socket> + Send + Sync>,
+    sockets: Mutex<Vec<PooledSocket>>,
+    // the events of every connection in the pool
+    events: broadcast::Sender<ConnectionEvent>,
+}
+
+impl SocketPool {
+    pub fn new(
+        new_socket: impl Fn() -> Box<dyn Socket> + Send + Sync + 'static,
+        assignment: SocketAssignment,
+    ) -> Self {
+        Self {
+            assignment,
+            new_socket: Box::new(new_socket),
+            sockets: Mutex::new(Vec::new()),
+            events: broadcast::channel(64).0,
+        }
+    }
+
+    pub fn assignment(&self) -> SocketAssignment {
+        self.assignment
+    }
+
+    pub fn sockets(&self) -> Vec<Arc<dyn Socket>> {
+        self.sockets
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|s| s.socket.clone())
+            .collect()
+    }
+
+    // connections opened later are included as well
+    pub fn connection_events(&self) -> broadcast::Receiver<ConnectionEvent> {
+        self.events.subscribe()
+    }
+
+    // has to be called from a runtime, the new connection's events are
+    // forwarded to the pool's
+    pub(crate) fn assign(&self, model: &SubscriptionModel) -> Arc<dyn Socket> {
+        let mut sockets = self.sockets.lock().unwrap();
+        let existing = sockets.iter_mut().find(|s| match self.assignment {
+            SocketAssignment::ByModel => s.model.as_ref() == Some(model),
+            SocketAssignment::MaxChannels(max) => s.channels < max.max(1),
+        });
+        if let Some(existing) = existing {
+            existing.channels += 1;
+            return existing.socket.clone();
+        }
+        let socket: Arc<dyn Socket> = Arc::from((self.new_socket)());
+        let mut events = socket.connection_events();
+        let forward = self.events.clone();
+        tokio::spawn(async move {
+            loop {
+                match events.recv().await {
+                    Ok(event) => {
+                        let _ = forward.send(event);
+                    },
+                    Err(broadcast::error::RecvError::Lagged(_)) => {},
+                    Err(broadcast::error::RecvError::Closed) => return,
+                }
+            }
+        });
+        sockets.push(PooledSocket {
+            socket: socket.clone(),
+            model: Some(model.clone()),
+            channels: 1,
+        });
+        socket
+    }
+
+    // connections stay open once they're empty, they're filled up again first
+    pub(crate) fn release(&self, socket: &Arc<dyn Socket>) {
+        let mut sockets = self.sockets.lock().unwrap();
+        if let Some(pooled) = sockets.iter_mut().find(|s| Arc::ptr_eq(&s.socket, socket)) {
+            pooled.channels = pooled.channels.saturating_sub(1);
+        }
+    }
+}
+
+impl Debug for SocketPool {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SocketPool")
+            .field("assignment", &self.assignment)
+            .field("sockets", &self.sockets.lock().unwrap().len())
+            .finish()
+    }
+}
+
+// subscriptions: Arc<RwLock<DashMap<String, Arc<SubscriptionState>>>>
 #[async_trait]
 pub trait Socket: Debug + Send + Sync + 'static {
     async fn init(&self, kit: Kit);
@@ -15,13 +290,31 @@ pub trait Socket: Debug + Send + Sync + 'static {
 
     fn get_connected(&'_ self) -> &'_ Event;
 
-    async fn add_subscription(&self, subscription: Arc<Subscription>);
+    async fn get_state(&self) -> ConnectionState;
+
+    async fn status(&self) -> SocketStatus;
+
+    fn connection_events(&self) -> broadcast::Receiver<ConnectionEvent>;
+
+    // sends an event to the connection's listeners, the kit reports the
+    // subscriptions it keeps alive itself with it
+    fn emit(&self, event: ConnectionEvent);
+
+    async fn add_subscription(&self, subscription: Arc<SubscriptionState>);
 
-    async fn remove_subscription(&self, subscription: Arc<Subscription>);
+    async fn remove_subscription(&self, subscription: Arc<SubscriptionState>);
 
-    async fn get_subscription(&self, channel: String) -> Option<Arc<Subscription>>;
+    async fn get_subscription(&self, channel: String) -> Option<Arc<SubscriptionState>>;
 
-    async fn send(&self, data: String) -> Result<(), String>;
+    // routes another channel to the subscription, used while it moves to a
+    // new channel so neither channel's messages are lost
+    async fn add_subscription_channel(&self, channel: String, subscription: Arc<SubscriptionState>);
+
+    async fn remove_subscription_channel(&self, channel: String);
+
+    // frames sent before the connection is established are queued until it
+    // is, see `OutgoingQueue`
+    async fn send(&self, data: String) -> Result<(), SendError>;
 
     async fn connect_ref(&self) -> Result<(), String>;
 
@@ -29,9 +322,185 @@ pub trait Socket: Debug + Send + Sync + 'static {
 
     async fn reconnect(&self) -> Result<(), String>;
 
-    async fn ping_pong(self);
+    // keeps the connection alive by ticking the protocol, see
+    // `PusherProtocol::poll`
+    fn start_ping_pong_task(&self);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
 
-    async fn call_later_pong(self);
+    use super::{
+        ConnectionEvent, ConnectionState, OutgoingQueue, SendError, Socket, SocketAssignment,
+        SocketPool, SocketStatus,
+    };
+    use crate::{
+        subscription::SubscriptionState,
+        test_util::{block_on, config, subscriptions, MockClient, MockSocket},
+        Kit, Object, SubscriptionEvent, SubscriptionModel,
+    };
 
-    fn start_ping_pong_task(&self);
+    // a kit with a pool that opens a connection per model, the connections
+    // are handed out as they're opened
+    fn pooled_kit() -> (Kit, Arc<Mutex<Vec<MockSocket>>>) {
+        let client = MockClient::new(|request| {
+            subscriptions(request).unwrap_or_else(|| Err("unexpected request".into()))
+        });
+        let opened = Arc::new(Mutex::new(Vec::new()));
+        let sockets = opened.clone();
+        let pool = SocketPool::new(
+            move || {
+                let mut sockets = sockets.lock().unwrap();
+                let socket = MockSocket::new(&format!("1.{}", sockets.len() + 1));
+                socket.set_state(ConnectionState::Established);
+                sockets.push(socket.clone());
+                Box::new(socket)
+            },
+            SocketAssignment::ByModel,
+        );
+        let kit = Kit::new(config(client).set_socket_pool(Some(pool)));
+        (kit, opened)
+    }
+
+    #[test]
+    fn frames_are_taken_in_order() {
+        let queue = OutgoingQueue::new();
+        let first = queue.push("first".into());
+        let second = queue.push("second".into());
+        assert_eq!(queue.len(), 2);
+
+        let frames = queue.take();
+        assert!(queue.is_empty());
+        assert_eq!(
+            frames.iter().map(|(f, _)| f.as_str()).collect::<Vec<_>>(),
+            vec!["first", "second"]
+        );
+        for (_, sender) in frames {
+            sender.send(Ok(())).unwrap();
+        }
+        block_on(async {
+            assert_eq!(first.await.unwrap(), Ok(()));
+            assert_eq!(second.await.unwrap(), Ok(()));
+        });
+    }
+
+    #[test]
+    fn abandoned_frames_report_why() {
+        let queue = OutgoingQueue::new();
+        let frame = queue.push("frame".into());
+        // nobody waiting for the result doesn't matter
+        drop(queue.push("dropped".into()));
+        queue.abandon("socket failed");
+        assert!(queue.is_empty());
+        assert_eq!(
+            block_on(frame).unwrap(),
+            Err(SendError::Abandoned("socket failed".into()))
+        );
+    }
+
+    #[test]
+    fn pool_events_are_forwarded() {
+        let (kit, opened) = pooled_kit();
+        block_on(async {
+            let mut events = kit.connection_events();
+            let _nations = kit
+                .subscribe(SubscriptionModel::Nation, SubscriptionEvent::Update)
+                .await
+                .unwrap();
+            let _cities = kit
+                .subscribe(SubscriptionModel::City, SubscriptionEvent::Update)
+                .await
+                .unwrap();
+            let opened = opened.lock().unwrap().clone();
+            assert_eq!(opened.len(), 2);
+            opened[0].emit(ConnectionEvent::Reconnecting);
+            opened[1].emit(ConnectionEvent::Closed(Some(4200)));
+            let first = tokio::time::timeout(Duration::from_secs(1), events.recv()).await;
+            let second = tokio::time::timeout(Duration::from_secs(1), events.recv()).await;
+            assert!(matches!(first, Ok(Ok(ConnectionEvent::Reconnecting))));
+            assert!(matches!(
+                second,
+                Ok(Ok(ConnectionEvent::Closed(Some(4200))))
+            ));
+        });
+    }
+
+    #[test]
+    fn pool_state_and_status_are_combined() {
+        let (kit, opened) = pooled_kit();
+        block_on(async {
+            assert_eq!(kit.connection_state().await, ConnectionState::Disconnected);
+            let _nations = kit
+                .subscribe(SubscriptionModel::Nation, SubscriptionEvent::Update)
+                .await
+                .unwrap();
+            let status = kit.socket_status().await;
+            assert_eq!(status.state, ConnectionState::Established);
+            assert_eq!(status.socket_id.as_deref(), Some("1.1"));
+
+            let _cities = kit
+                .subscribe(SubscriptionModel::City, SubscriptionEvent::Update)
+                .await
+                .unwrap();
+            opened.lock().unwrap()[1].set_state(ConnectionState::Reconnecting);
+            assert_eq!(kit.connection_state().await, ConnectionState::Reconnecting);
+            let status = kit.socket_status().await;
+            assert_eq!(status.state, ConnectionState::Reconnecting);
+            // there's no one id for several connections
+            assert_eq!(status.socket_id, None);
+            assert_eq!(status.channel_messages.len(), 2);
+            assert_eq!(kit.sockets().len(), 2);
+        });
+    }
+
+    #[test]
+    fn unsubscribing_doesnt_open_a_connection() {
+        let (kit, opened) = pooled_kit();
+        block_on(async {
+            let state = Arc::new(SubscriptionState::new(
+                SubscriptionModel::Nation,
+                SubscriptionEvent::Update,
+                Object::new(),
+                "https://test/subscribe/nation/update".into(),
+            ));
+            kit.unsubscribe_state(&state).await.unwrap();
+            assert!(opened.lock().unwrap().is_empty());
+            assert!(kit.sockets().is_empty());
+        });
+    }
+
+    #[test]
+    fn statuses_are_combined() {
+        let status = |state, id: &str, rtt, reconnects, channel: &str| SocketStatus {
+            state,
+            socket_id: Some(id.into()),
+            ping_rtt: Some(Duration::from_millis(rtt)),
+            since_last_message: None,
+            activity_timeout: Duration::from_secs(rtt),
+            reconnects,
+            channel_messages: HashMap::from([(channel.to_string(), reconnects)]),
+        };
+        let combined = SocketStatus::combine(vec![
+            status(ConnectionState::Established, "1.1", 20, 1, "a"),
+            status(ConnectionState::Connecting, "1.2", 50, 2, "b"),
+        ]);
+        assert_eq!(combined.state, ConnectionState::Connecting);
+        assert_eq!(combined.socket_id, None);
+        assert_eq!(combined.ping_rtt, Some(Duration::from_millis(50)));
+        assert_eq!(combined.activity_timeout, Duration::from_secs(20));
+        assert_eq!(combined.reconnects, 3);
+        assert_eq!(
+            combined.channel_messages,
+            HashMap::from([("a".to_string(), 1), ("b".to_string(), 2)])
+        );
+
+        let combined = SocketStatus::combine(Vec::new());
+        assert_eq!(combined.state, ConnectionState::Disconnected);
+        assert_eq!(combined.activity_timeout, Duration::ZERO);
+    }
 }
@@ -1,10 +1,32 @@
-use std::{collections::VecDeque, fmt::Debug};
+use std::{
+    collections::{HashSet, VecDeque},
+    fmt::Debug,
+    future::Future,
+    marker::PhantomData,
+    pin::Pin,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, AtomicI64, AtomicU64, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+};
 
-use tokio::sync::{Mutex, Notify};
+use futures_util::{future::select, Stream};
+use tokio::sync::{
+    broadcast::{self, error::RecvError},
+    Mutex, Notify,
+};
 
-use crate::{event::Event, Object};
+use crate::{
+    event::Event,
+    journal::SubscriptionJournal,
+    payload::SubscriptionPayload,
+    socket::{ConnectionEvent, Socket, SocketStatus},
+    Kit, Object,
+};
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum SubscriptionModel {
     Account,
     Alliance,
@@ -25,6 +47,95 @@ pub enum SubscriptionModel {
     Tradeprice,
 }
 
+impl SubscriptionModel {
+    // the filters the subscription endpoint accepts for the model
+    pub fn filters(&self) -> &'static [&'static str] {
+        match self {
+            Self::Account => &["id"],
+            Self::Alliance => &["id"],
+            Self::AlliancePosition => &["id", "alliance_id"],
+            Self::Bankrec => &[
+                "id",
+                "sender_id",
+                "sender_type",
+                "receiver_id",
+                "receiver_type",
+            ],
+            Self::BBGame => &[
+                "id",
+                "home_id",
+                "away_id",
+                "home_nation_id",
+                "away_nation_id",
+            ],
+            Self::BBTeam => &["id", "nation_id"],
+            Self::Bounty => &["id", "nation_id"],
+            Self::City => &["id", "nation_id"],
+            Self::Embargo => &["id", "sender_id", "receiver_id"],
+            Self::Nation => &["id", "alliance_id"],
+            Self::TaxBracket => &["id", "alliance_id"],
+            Self::Trade => &["id", "sender_id", "receiver_id"],
+            Self::TreasureTrade => &["id", "sender_id", "receiver_id"],
+            Self::Treaty => &["id", "alliance_id"],
+            Self::WarAttack => &["id", "war_id", "att_id", "def_id"],
+            Self::War => &[
+                "id",
+                "att_id",
+                "def_id",
+                "att_alliance_id",
+                "def_alliance_id",
+                "alliance_id",
+            ],
+            Self::Tradeprice => &["id"],
+        }
+    }
+
+    // the root query listing records of the model, used to backfill
+    #[cfg(feature = "async")]
+    pub(crate) fn query_name(&self) -> Option<&'static str> {
+        match self {
+            Self::Alliance => Some("alliances"),
+            Self::Bankrec => Some("bankrecs"),
+            Self::BBGame => Some("baseball_games"),
+            Self::BBTeam => Some("baseball_teams"),
+            Self::Bounty => Some("bounties"),
+            Self::City => Some("cities"),
+            Self::Embargo => Some("embargoes"),
+            Self::Nation => Some("nations"),
+            Self::Trade => Some("trades"),
+            Self::TreasureTrade => Some("treasure_trades"),
+            Self::Treaty => Some("treaties"),
+            Self::WarAttack => Some("warattacks"),
+            Self::War => Some("wars"),
+            Self::Tradeprice => Some("tradeprices"),
+            Self::Account | Self::AlliancePosition | Self::TaxBracket => None,
+        }
+    }
+
+    // the GraphQL type of the records, its fields are looked up before
+    // backfilling
+    #[cfg(feature = "async")]
+    pub(crate) fn type_name(&self) -> Option<&'static str> {
+        match self {
+            Self::Alliance => Some("Alliance"),
+            Self::Bankrec => Some("Bankrec"),
+            Self::BBGame => Some("BBGame"),
+            Self::BBTeam => Some("BBTeam"),
+            Self::Bounty => Some("Bounty"),
+            Self::City => Some("City"),
+            Self::Embargo => Some("Embargo"),
+            Self::Nation => Some("Nation"),
+            Self::Trade => Some("Trade"),
+            Self::TreasureTrade => Some("TreasureTrade"),
+            Self::Treaty => Some("Treaty"),
+            Self::WarAttack => Some("WarAttack"),
+            Self::War => Some("War"),
+            Self::Tradeprice => Some("Tradeprice"),
+            Self::Account | Self::AlliancePosition | Self::TaxBracket => None,
+        }
+    }
+}
+
 impl ToString for SubscriptionModel {
     fn to_string(&self) -> String {
         match self {
@@ -50,6 +161,33 @@ impl ToString for SubscriptionModel {
     }
 }
 
+impl FromStr for SubscriptionModel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "account" => Self::Account,
+            "alliance" => Self::Alliance,
+            "alliance_position" => Self::AlliancePosition,
+            "bankrec" => Self::Bankrec,
+            "bbgame" => Self::BBGame,
+            "bbteam" => Self::BBTeam,
+            "bounty" => Self::Bounty,
+            "city" => Self::City,
+            "embargo" => Self::Embargo,
+            "nation" => Self::Nation,
+            "tax_bracket" => Self::TaxBracket,
+            "trade" => Self::Trade,
+            "treasure_trade" => Self::TreasureTrade,
+            "treaty" => Self::Treaty,
+            "warattack" => Self::WarAttack,
+            "war" => Self::War,
+            "tradeprice" => Self::Tradeprice,
+            _ => return Err(format!("unknown subscription model {}", s)),
+        })
+    }
+}
+
 #[derive(Clone, Debug)]
 pub enum SubscriptionEvent {
     Create,
@@ -68,27 +206,242 @@ impl ToString for SubscriptionEvent {
     }
 }
 
+#[derive(Clone, Debug)]
+pub struct SubscriptionMessage<T = Object> {
+    pub model: SubscriptionModel,
+    // the Pusher event name without the `BULK_` prefix
+    pub event: String,
+    pub bulk: bool,
+    pub channel: String,
+    // seconds since the unix epoch, as returned by `Config::now`
+    pub received: u64,
+    // set on records that were queried after a reconnect rather than received
+    // from the socket
+    pub backfill: bool,
+    // set when the subscription has a journal, the message is delivered
+    // again until it's passed to `Subscription::ack`
+    pub journal_id: Option<u64>,
+    pub data: T,
+}
+
+impl SubscriptionMessage {
+    pub fn into_payload<T: SubscriptionPayload>(self) -> SubscriptionMessage<T> {
+        SubscriptionMessage {
+            data: T::from_object(&self.data),
+            model: self.model,
+            event: self.event,
+            bulk: self.bulk,
+            channel: self.channel,
+            received: self.received,
+            backfill: self.backfill,
+            journal_id: self.journal_id,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    // waits for the consumer, the socket stops reading in the meantime so
+    // every other subscription on it is held up as well and pings go
+    // unanswered, a consumer that falls behind for longer than the activity
+    // timeout gets the connection closed by the server, only use it for a
+    // socket the subscription has to itself
+    Block,
+    DropOldest,
+    DropNewest,
+    // closes the queue and unsubscribes
+    Disconnect,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct QueueLimit {
+    pub capacity: usize,
+    pub overflow: OverflowPolicy,
+}
+
+impl QueueLimit {
+    pub fn new(capacity: usize, overflow: OverflowPolicy) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            overflow,
+        }
+    }
+}
+
 pub struct SubscriptionQueue {
-    queue: Mutex<VecDeque<Object>>,
+    queue: Mutex<VecDeque<SubscriptionMessage>>,
     notify: Notify,
+    space: Notify,
+    closed: AtomicBool,
+    limit: Option<QueueLimit>,
+    dropped: AtomicU64,
+    // created for the first fan-out receiver, dropped on close
+    fanout: std::sync::Mutex<Option<broadcast::Sender<SubscriptionMessage>>>,
+    // set once the subscription is only read through receivers
+    fanout_only: AtomicBool,
 }
 
+const FANOUT_CAPACITY: usize = 1024;
+
 impl SubscriptionQueue {
     pub fn new() -> Self {
+        Self::with_limit(None)
+    }
+
+    pub fn with_limit(limit: Option<QueueLimit>) -> Self {
         Self {
             queue: Mutex::new(VecDeque::new()),
             notify: Notify::new(),
+            space: Notify::new(),
+            closed: AtomicBool::new(false),
+            limit,
+            dropped: AtomicU64::new(0),
+            fanout: std::sync::Mutex::new(None),
+            fanout_only: AtomicBool::new(false),
         }
     }
 
-    pub async fn push(&self, object: Object) {
-        self.queue.lock().await.push_back(object);
-        self.notify.notify_waiters();
+    pub fn subscribe(&self) -> broadcast::Receiver<SubscriptionMessage> {
+        let mut fanout = self.fanout.lock().unwrap();
+        match fanout.as_ref() {
+            Some(sender) => sender.subscribe(),
+            None => {
+                let (sender, receiver) = broadcast::channel(FANOUT_CAPACITY);
+                // a closed queue hands out receivers that are closed already
+                if !self.is_closed() {
+                    fanout.replace(sender);
+                }
+                receiver
+            },
+        }
     }
 
-    pub async fn pop(&self) -> Option<Object> {
-        self.wait().await;
-        self.queue.lock().await.pop_front()
+    // from here on messages only go to fan-out receivers, the ones queued
+    // already are handed to the returned receiver first
+    pub async fn subscribe_only(&self) -> broadcast::Receiver<SubscriptionMessage> {
+        let mut queue = self.queue.lock().await;
+        let receiver = self.subscribe();
+        self.fanout_only.store(true, Ordering::Release);
+        for message in queue.drain(..) {
+            self.fan_out(&message);
+        }
+        self.space.notify_waiters();
+        receiver
+    }
+
+    fn fan_out(&self, message: &SubscriptionMessage) {
+        if let Some(sender) = self.fanout.lock().unwrap().as_ref() {
+            if sender.receiver_count() > 0 {
+                let _ = sender.send(message.clone());
+            }
+        }
+    }
+
+    fn enqueue(&self, queue: &mut VecDeque<SubscriptionMessage>, message: SubscriptionMessage) {
+        self.fan_out(&message);
+        if !self.fanout_only.load(Ordering::Acquire) {
+            queue.push_back(message);
+        }
+    }
+
+    pub fn limit(&self) -> Option<QueueLimit> {
+        self.limit
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Acquire)
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::Acquire)
+    }
+
+    pub async fn len(&self) -> usize {
+        self.queue.lock().await.len()
+    }
+
+    pub async fn is_empty(&self) -> bool {
+        self.queue.lock().await.is_empty()
+    }
+
+    // only fails when the queue overflows with `OverflowPolicy::Disconnect`,
+    // messages pushed to a closed queue are discarded, fan-out receivers get
+    // every message that is queued as well but none that are dropped
+    pub async fn push(&self, message: SubscriptionMessage) -> Result<(), String> {
+        self.offer(message).await.map(|_| ())
+    }
+
+    // returns whether the message was delivered rather than dropped
+    async fn offer(&self, message: SubscriptionMessage) -> Result<bool, String> {
+        if self.is_closed() {
+            return Ok(false);
+        }
+        loop {
+            let space = self.space.notified();
+            {
+                let mut queue = self.queue.lock().await;
+                if self.is_closed() {
+                    return Ok(false);
+                }
+                if self.fanout_only.load(Ordering::Acquire) {
+                    self.fan_out(&message);
+                    return Ok(true);
+                }
+                match self.limit {
+                    Some(limit) if queue.len() >= limit.capacity => match limit.overflow {
+                        OverflowPolicy::Block => {},
+                        OverflowPolicy::DropOldest => {
+                            queue.pop_front();
+                            self.enqueue(&mut queue, message);
+                            self.dropped.fetch_add(1, Ordering::AcqRel);
+                            self.notify.notify_waiters();
+                            return Ok(true);
+                        },
+                        OverflowPolicy::DropNewest => {
+                            self.dropped.fetch_add(1, Ordering::AcqRel);
+                            return Ok(false);
+                        },
+                        OverflowPolicy::Disconnect => {
+                            self.dropped.fetch_add(1, Ordering::AcqRel);
+                            drop(queue);
+                            self.close();
+                            return Err("subscription queue overflowed".into());
+                        },
+                    },
+                    _ => {
+                        self.enqueue(&mut queue, message);
+                        self.notify.notify_waiters();
+                        return Ok(true);
+                    },
+                }
+            }
+            space.await;
+        }
+    }
+
+    // returns `None` once the queue is closed and everything in it was popped
+    pub async fn pop(&self) -> Option<SubscriptionMessage> {
+        loop {
+            let notified = self.notify.notified();
+            {
+                let mut queue = self.queue.lock().await;
+                if let Some(message) = queue.pop_front() {
+                    self.space.notify_waiters();
+                    return Some(message);
+                }
+                if self.is_closed() {
+                    return None;
+                }
+            }
+            notified.await;
+        }
+    }
+
+    pub fn close(&self) {
+        self.closed.store(true, Ordering::Release);
+        self.fanout.lock().unwrap().take();
+        self.notify.notify_waiters();
+        self.space.notify_waiters();
     }
 
     pub async fn wait(&self) {
@@ -102,10 +455,43 @@ impl SubscriptionQueue {
         .await
     }
 
-    pub async fn extend(&self, iter: impl Iterator<Item = Object>) {
-        self.queue.lock().await.extend(iter);
+    // queues the messages regardless of the limit, they are only fanned out
+    // when nothing reads the queue itself
+    pub(crate) async fn seed(&self, messages: Vec<SubscriptionMessage>) {
+        if messages.is_empty() {
+            return;
+        }
+        let mut queue = self.queue.lock().await;
+        if self.fanout_only.load(Ordering::Acquire) {
+            messages.iter().for_each(|message| self.fan_out(message));
+        } else {
+            queue.extend(messages);
+        }
+        drop(queue);
         self.notify.notify_waiters();
     }
+
+    pub async fn extend(
+        &self,
+        iter: impl Iterator<Item = SubscriptionMessage>,
+    ) -> Result<(), String> {
+        if self.limit.is_none() {
+            let mut queue = self.queue.lock().await;
+            if self.is_closed() {
+                return Ok(());
+            }
+            for message in iter {
+                self.enqueue(&mut queue, message);
+            }
+            drop(queue);
+            self.notify.notify_waiters();
+            return Ok(());
+        }
+        for message in iter {
+            self.push(message).await?;
+        }
+        Ok(())
+    }
 }
 
 impl Default for SubscriptionQueue {
@@ -114,16 +500,54 @@ impl Default for SubscriptionQueue {
     }
 }
 
-pub struct Subscription {
+// the auth a channel was subscribed with, it's only good for the socket id it
+// was signed for so a new connection needs a new one
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChannelAuth {
+    pub channel: String,
+    pub socket_id: String,
+    pub auth: String,
+}
+
+// the state of a subscription that is shared with the socket, which routes
+// messages to it by channel
+pub struct SubscriptionState {
     pub(crate) model: SubscriptionModel,
     pub(crate) event: SubscriptionEvent,
-    pub(crate) filters: Object,
+    filters: std::sync::RwLock<Object>,
     pub channel: Mutex<String>,
+    // when the channel was requested, from `Config::now`
+    channel_requested: AtomicU64,
+    auth: std::sync::Mutex<Option<ChannelAuth>>,
     pub succeeded: Event,
+    // set with `error` when the server rejected the subscribe
+    failed: Event,
+    error: std::sync::Mutex<Option<String>>,
+    // the channel being moved to by `Subscription::update_filters`
+    next_channel: Mutex<Option<String>>,
+    next_succeeded: Event,
+    next_failed: Event,
+    next_error: std::sync::Mutex<Option<String>>,
+    // the records recently received while both channels are routed here,
+    // with the channel they came from
+    seen: std::sync::Mutex<Option<VecDeque<(String, String, i64)>>>,
     pub queue: SubscriptionQueue,
+    // shared with the blocking tasks that write to it
+    journal: Option<Arc<SubscriptionJournal>>,
+    // the connection the subscription was assigned to by the kit
+    socket: std::sync::Mutex<Option<Arc<dyn Socket>>>,
+    unsubscribed: AtomicBool,
+    // the highest record id received, zero if there wasn't any
+    watermark: AtomicI64,
+    // the watermark when the subscription was last subscribed, records that
+    // arrive afterwards don't mean the ones before them were received
+    subscribed_watermark: AtomicI64,
+    // ids of the records recently received by a create subscription, a
+    // backfilled record may arrive from the socket as well
+    delivered: std::sync::Mutex<(HashSet<i64>, VecDeque<i64>)>,
 }
 
-impl Subscription {
+impl SubscriptionState {
     pub fn new(
         model: SubscriptionModel,
         event: SubscriptionEvent,
@@ -133,10 +557,47 @@ impl Subscription {
         Self {
             model,
             event,
-            filters,
+            filters: std::sync::RwLock::new(filters),
             channel: Mutex::new(channel),
+            channel_requested: AtomicU64::new(0),
+            auth: std::sync::Mutex::new(None),
             succeeded: Event::new(),
+            failed: Event::new(),
+            error: std::sync::Mutex::new(None),
+            next_channel: Mutex::new(None),
+            next_succeeded: Event::new(),
+            next_failed: Event::new(),
+            next_error: std::sync::Mutex::new(None),
+            seen: std::sync::Mutex::new(None),
             queue: SubscriptionQueue::new(),
+            journal: None,
+            socket: std::sync::Mutex::new(None),
+            unsubscribed: AtomicBool::new(false),
+            watermark: AtomicI64::new(0),
+            subscribed_watermark: AtomicI64::new(0),
+            delivered: std::sync::Mutex::new((HashSet::new(), VecDeque::new())),
+        }
+    }
+
+    pub(crate) fn set_queue_limit(mut self, limit: Option<QueueLimit>) -> Self {
+        self.queue = SubscriptionQueue::with_limit(limit);
+        self
+    }
+
+    pub(crate) fn set_journal(mut self, journal: Option<SubscriptionJournal>) -> Self {
+        self.journal = journal.map(Arc::new);
+        self
+    }
+
+    pub fn journal(&self) -> Option<&SubscriptionJournal> {
+        self.journal.as_deref()
+    }
+
+    // queues what the journal has that wasn't acknowledged, before anything
+    // new arrives, none of it was lost before so it isn't dropped now
+    pub(crate) async fn replay(&self) {
+        if let Some(journal) = &self.journal {
+            self.queue.seed(journal.unacked()).await;
         }
     }
 
@@ -144,25 +605,1549 @@ impl Subscription {
         *self.channel.lock().await = channel;
     }
 
-    pub async fn next(&self) -> Option<Object> {
-        self.queue.pop().await
+    pub(crate) fn set_channel_requested(&self, now: u64) {
+        self.channel_requested.store(now, Ordering::Release);
+    }
+
+    pub fn channel_age(&self, now: u64) -> u64 {
+        now.saturating_sub(self.channel_requested.load(Ordering::Acquire))
+    }
+
+    pub fn auth(&self) -> Option<ChannelAuth> {
+        self.auth.lock().unwrap().clone()
+    }
+
+    pub(crate) fn set_auth(&self, auth: ChannelAuth) {
+        self.auth.lock().unwrap().replace(auth);
+    }
+
+    pub fn socket(&self) -> Option<Arc<dyn Socket>> {
+        self.socket.lock().unwrap().clone()
+    }
+
+    pub(crate) fn set_socket(&self, socket: Arc<dyn Socket>) {
+        self.socket.lock().unwrap().replace(socket);
+    }
+
+    pub fn filters(&self) -> Object {
+        self.filters.read().unwrap().clone()
+    }
+
+    pub async fn set_succeeded(&self, channel: &str) {
+        if self.next_channel.lock().await.as_deref() == Some(channel) {
+            self.next_succeeded.set().await;
+        } else {
+            self.succeeded.set().await;
+        }
+    }
+
+    pub async fn set_error(&self, channel: &str, reason: String) {
+        if self.next_channel.lock().await.as_deref() == Some(channel) {
+            self.next_error.lock().unwrap().replace(reason);
+            self.next_failed.set().await;
+        } else {
+            self.error.lock().unwrap().replace(reason);
+            self.failed.set().await;
+        }
+    }
+
+    pub(crate) async fn begin_subscribe(&self) {
+        self.subscribed_watermark
+            .store(self.watermark.load(Ordering::Acquire), Ordering::Release);
+        self.succeeded.clear().await;
+        self.failed.clear().await;
+        self.error.lock().unwrap().take();
+    }
+
+    // resolves once the server accepted or rejected the subscribe
+    pub(crate) async fn wait_subscribed(&self) -> Result<(), String> {
+        select(
+            Box::pin(self.succeeded.wait()),
+            Box::pin(self.failed.wait()),
+        )
+        .await;
+        match self.error.lock().unwrap().take() {
+            Some(error) => Err(error),
+            None => Ok(()),
+        }
+    }
+
+    pub(crate) async fn begin_switch(&self, channel: String) {
+        self.next_succeeded.clear().await;
+        self.next_failed.clear().await;
+        self.next_error.lock().unwrap().take();
+        self.next_channel.lock().await.replace(channel);
+        self.seen.lock().unwrap().replace(VecDeque::new());
+    }
+
+    pub(crate) async fn wait_switch(&self) -> Result<(), String> {
+        select(
+            Box::pin(self.next_succeeded.wait()),
+            Box::pin(self.next_failed.wait()),
+        )
+        .await;
+        match self.next_error.lock().unwrap().take() {
+            Some(error) => Err(error),
+            None => Ok(()),
+        }
+    }
+
+    // makes the next channel the current one, `None` abandons the switch
+    pub(crate) async fn finish_switch(&self, filters: Option<Object>) {
+        let next_channel = self.next_channel.lock().await.take();
+        if let (Some(channel), Some(filters)) = (next_channel, filters) {
+            *self.channel.lock().await = channel;
+            *self.filters.write().unwrap() = filters;
+        }
+    }
+
+    pub(crate) async fn next_channel(&self) -> Option<String> {
+        self.next_channel.lock().await.clone()
+    }
+
+    pub(crate) fn end_dedupe(&self) {
+        self.seen.lock().unwrap().take();
+    }
+
+    // returns true if the same event for the record already arrived from the
+    // other channel, each one is only matched once so events that are sent
+    // twice on purpose still come through
+    fn duplicate(&self, channel: &str, event: &str, data: &Object) -> bool {
+        const SEEN_CAPACITY: usize = 1024;
+        let mut seen = self.seen.lock().unwrap();
+        let (seen, id) = match (seen.as_mut(), record_id(data)) {
+            (Some(seen), Some(id)) => (seen, id),
+            _ => return false,
+        };
+        let other = seen
+            .iter()
+            .position(|(c, e, i)| c != channel && e == event && *i == id);
+        if let Some(other) = other {
+            seen.remove(other);
+            return true;
+        }
+        if seen.len() >= SEEN_CAPACITY {
+            seen.pop_front();
+        }
+        seen.push_back((channel.into(), event.into(), id));
+        false
+    }
+
+    // returns false if the subscription was already unsubscribed
+    pub(crate) fn set_unsubscribed(&self) -> bool {
+        !self.unsubscribed.swap(true, Ordering::AcqRel)
+    }
+
+    pub fn is_unsubscribed(&self) -> bool {
+        self.unsubscribed.load(Ordering::Acquire)
+    }
+
+    pub fn watermark(&self) -> Option<i64> {
+        match self.watermark.load(Ordering::Acquire) {
+            0 => None,
+            watermark => Some(watermark),
+        }
+    }
+
+    // the watermark a backfill after resubscribing starts from
+    #[cfg(feature = "async")]
+    pub(crate) fn subscribed_watermark(&self) -> Option<i64> {
+        match self.subscribed_watermark.load(Ordering::Acquire) {
+            0 => None,
+            watermark => Some(watermark),
+        }
+    }
+
+    // only records that were delivered count, a backfill can recover those
+    // that were dropped before the watermark went past them
+    fn update_watermark(&self, id: Option<i64>) {
+        let id = match id {
+            Some(id) => id,
+            None => return,
+        };
+        self.watermark.fetch_max(id, Ordering::AcqRel);
+        if !matches!(self.event, SubscriptionEvent::Create) {
+            return;
+        }
+        const DELIVERED_CAPACITY: usize = 4096;
+        let (delivered, order) = &mut *self.delivered.lock().unwrap();
+        if delivered.insert(id) {
+            if order.len() >= DELIVERED_CAPACITY {
+                if let Some(oldest) = order.pop_front() {
+                    delivered.remove(&oldest);
+                }
+            }
+            order.push_back(id);
+        }
     }
 
-    pub async fn push(&self, object: Object) {
-        self.queue.push(object).await
+    // records are only created once, so a create with an id that was already
+    // received is the same record from both the socket and a backfill
+    fn delivered(&self, data: &Object) -> bool {
+        if !matches!(self.event, SubscriptionEvent::Create) {
+            return false;
+        }
+        match record_id(data) {
+            Some(id) => self.delivered.lock().unwrap().0.contains(&id),
+            None => false,
+        }
     }
 
-    pub async fn extend(&self, iter: impl Iterator<Item = Object>) {
-        self.queue.extend(iter).await
+    pub async fn push(&self, mut message: SubscriptionMessage) -> Result<(), String> {
+        let id = record_id(&message.data);
+        // the socket's read task shouldn't wait on the disk itself
+        if let Some(journal) = self.journal.clone() {
+            message =
+                tokio::task::spawn_blocking(move || journal.append(&mut message).map(|_| message))
+                    .await
+                    .map_err(|e| e.to_string())??;
+        }
+        let journal_id = message.journal_id;
+        let delivered = self.queue.offer(message).await;
+        if let Ok(true) = delivered {
+            self.update_watermark(id);
+        } else if let (Some(journal), Some(journal_id)) = (self.journal.clone(), journal_id) {
+            // a dropped message mustn't come back when the journal is replayed
+            tokio::task::spawn_blocking(move || journal.ack(journal_id))
+                .await
+                .map_err(|e| e.to_string())??;
+        }
+        delivered.map(|_| ())
+    }
+
+    pub async fn extend(
+        &self,
+        iter: impl Iterator<Item = SubscriptionMessage>,
+    ) -> Result<(), String> {
+        for message in iter {
+            self.push(message).await?;
+        }
+        Ok(())
+    }
+
+    // records are pushed in id order, skipping those that already arrived
+    // from the socket after resubscribing
+    #[cfg(feature = "async")]
+    pub(crate) async fn push_backfill(
+        &self,
+        channel: String,
+        received: u64,
+        mut records: Vec<Object>,
+    ) -> Result<(), String> {
+        records.retain(|r| !self.delivered(r));
+        records.sort_by_key(record_id);
+        let event = self.event.to_string();
+        self.extend(records.into_iter().map(|data| SubscriptionMessage {
+            model: self.model.clone(),
+            event: event.clone(),
+            bulk: false,
+            channel: channel.clone(),
+            received,
+            backfill: true,
+            journal_id: None,
+            data,
+        }))
+        .await
+    }
+
+    // pushes the payloads of an event the protocol received, each one as a
+    // message of its own
+    pub async fn push_event(
+        &self,
+        event: &str,
+        channel: String,
+        received: u64,
+        bulk: bool,
+        data: Vec<Object>,
+    ) -> Result<(), String> {
+        let messages = data
+            .into_iter()
+            .filter(|data| !self.duplicate(&channel, event, data) && !self.delivered(data))
+            .map(|data| SubscriptionMessage {
+                model: self.model.clone(),
+                event: event.into(),
+                bulk,
+                channel: channel.clone(),
+                received,
+                backfill: false,
+                journal_id: None,
+                data,
+            })
+            .collect::<Vec<_>>();
+        self.extend(messages.into_iter()).await
     }
 }
 
-impl Debug for Subscription {
+fn record_id(data: &Object) -> Option<i64> {
+    data.get("id").and_then(|id| id.value().as_i64())
+}
+
+impl Debug for SubscriptionState {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.debug_struct("Subscription")
+        f.debug_struct("SubscriptionState")
             .field("model", &self.model)
             .field("event", &self.event)
-            .field("filters", &self.filters)
+            .field("filters", &self.filters())
+            .finish()
+    }
+}
+
+struct SubscriptionHandle {
+    state: Arc<SubscriptionState>,
+    kit: Kit,
+}
+
+impl Drop for SubscriptionHandle {
+    fn drop(&mut self) {
+        if self.state.is_unsubscribed() {
+            return;
+        }
+        let state = self.state.clone();
+        let kit = self.kit.clone();
+        let unsubscribe = async move {
+            let _ = kit.unsubscribe_state(&state).await;
+        };
+        // dropping outside of a runtime can't send the unsubscribe, the socket
+        // is gone with it anyways, unless the subscription was made from sync
+        // code on the config's runtime
+        match tokio::runtime::Handle::try_current() {
+            Ok(runtime) => {
+                runtime.spawn(unsubscribe);
+            },
+            #[cfg(feature = "sync")]
+            Err(_) => {
+                let _ = (self.kit.config.spawn)(Box::pin(unsubscribe));
+            },
+            #[cfg(not(feature = "sync"))]
+            Err(_) => {},
+        }
+    }
+}
+
+type NextFuture = Pin<Box<dyn Future<Output = Option<SubscriptionMessage>> + Send + Sync>>;
+
+// the last clone of a subscription to be dropped unsubscribes from the channel
+pub struct Subscription {
+    handle: Arc<SubscriptionHandle>,
+    next: Option<NextFuture>,
+}
+
+impl Subscription {
+    pub(crate) fn new(kit: Kit, state: Arc<SubscriptionState>) -> Self {
+        Self {
+            handle: Arc::new(SubscriptionHandle { state, kit }),
+            next: None,
+        }
+    }
+
+    pub fn state(&self) -> &Arc<SubscriptionState> {
+        &self.handle.state
+    }
+
+    pub fn model(&self) -> &SubscriptionModel {
+        &self.handle.state.model
+    }
+
+    pub fn event(&self) -> &SubscriptionEvent {
+        &self.handle.state.event
+    }
+
+    pub async fn channel(&self) -> String {
+        self.handle.state.channel.lock().await.clone()
+    }
+
+    pub async fn next(&self) -> Option<SubscriptionMessage> {
+        self.handle.state.queue.pop().await
+    }
+
+    // blocks the thread until the next message, for sync code, `None` once
+    // the subscription ended, the message is awaited on `Config::spawn`'s
+    // runtime and calling it from inside a runtime is an error
+    #[cfg(feature = "sync")]
+    pub fn recv_blocking(&self) -> Result<Option<SubscriptionMessage>, String> {
+        let state = self.handle.state.clone();
+        self.handle
+            .kit
+            .block_on(async move { state.queue.pop().await })
+    }
+
+    // the messages from `recv_blocking` until the subscription ends or one
+    // can't be received
+    #[cfg(feature = "sync")]
+    pub fn iter_blocking(&self) -> BlockingIter<'_> {
+        BlockingIter {
+            subscription: self,
+            done: false,
+        }
+    }
+
+    pub fn queue_limit(&self) -> Option<QueueLimit> {
+        self.handle.state.queue.limit()
+    }
+
+    // marks a message as handled so the journal doesn't deliver it again,
+    // does nothing without a journal, the journal is written on a blocking
+    // task
+    pub async fn ack(&self, journal_id: u64) -> Result<(), String> {
+        match self.handle.state.journal.clone() {
+            Some(journal) => tokio::task::spawn_blocking(move || journal.ack(journal_id))
+                .await
+                .map_err(|e| e.to_string())?,
+            None => Ok(()),
+        }
+    }
+
+    // like `ack` but writes the journal on this thread, for sync code
+    #[cfg(feature = "sync")]
+    pub fn ack_blocking(&self, journal_id: u64) -> Result<(), String> {
+        match self.handle.state.journal() {
+            Some(journal) => journal.ack(journal_id),
+            None => Ok(()),
+        }
+    }
+
+    // moves the subscription to a channel with the new filters, messages
+    // from both channels are deduplicated while they overlap
+    pub async fn update_filters(&self, filters: Object) -> Result<(), String> {
+        self.handle
+            .kit
+            .update_filters(&self.handle.state, filters)
+            .await
+    }
+
+    // messages discarded because the queue was full
+    pub fn dropped(&self) -> u64 {
+        self.handle.state.queue.dropped()
+    }
+
+    // turns the subscription into receivers that each see every message from
+    // here on and keep the channel subscribed like a clone would, what was
+    // queued already goes to the first receiver and nothing is queued for
+    // `next` anymore, so clones of the subscription stop getting messages
+    pub async fn subscribe_receiver(self) -> SubscriptionReceiver {
+        SubscriptionReceiver {
+            receiver: self.handle.state.queue.subscribe_only().await,
+            subscription: self,
+        }
+    }
+
+    // the events of the connection the subscription is on
+    pub fn connection_events(&self) -> broadcast::Receiver<ConnectionEvent> {
+        match self.handle.state.socket() {
+            Some(socket) => socket.connection_events(),
+            None => self.handle.kit.connection_events(),
+        }
+    }
+
+    pub async fn socket_status(&self) -> SocketStatus {
+        match self.handle.state.socket() {
+            Some(socket) => socket.status().await,
+            None => self.handle.kit.socket_status().await,
+        }
+    }
+}
+
+impl Clone for Subscription {
+    fn clone(&self) -> Self {
+        Self {
+            handle: self.handle.clone(),
+            next: None,
+        }
+    }
+}
+
+impl Stream for Subscription {
+    type Item = SubscriptionMessage;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let state = self.handle.state.clone();
+        let next = self
+            .next
+            .get_or_insert_with(|| Box::pin(async move { state.queue.pop().await }));
+        let poll = next.as_mut().poll(cx);
+        if poll.is_ready() {
+            self.next = None;
+        }
+        poll
+    }
+}
+
+// blocks on each message like `recv_blocking`, ending when the subscription
+// does or a message can't be received, use `iter_blocking` to see why
+#[cfg(feature = "sync")]
+impl Iterator for Subscription {
+    type Item = SubscriptionMessage;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.recv_blocking().ok().flatten()
+    }
+}
+
+#[cfg(feature = "sync")]
+pub struct BlockingIter<'a> {
+    subscription: &'a Subscription,
+    done: bool,
+}
+
+#[cfg(feature = "sync")]
+impl Iterator for BlockingIter<'_> {
+    type Item = Result<SubscriptionMessage, String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let res = self.subscription.recv_blocking().transpose();
+        self.done = !matches!(res, Some(Ok(_)));
+        res
+    }
+}
+
+impl Debug for Subscription {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Subscription")
+            .field("model", &self.handle.state.model)
+            .field("event", &self.handle.state.event)
+            .field("filters", &self.handle.state.filters())
             .finish()
     }
 }
+
+pub struct SubscriptionReceiver {
+    subscription: Subscription,
+    receiver: broadcast::Receiver<SubscriptionMessage>,
+}
+
+impl SubscriptionReceiver {
+    pub fn subscription(&self) -> &Subscription {
+        &self.subscription
+    }
+
+    // `RecvError::Lagged` tells how many messages were missed by falling
+    // behind, `RecvError::Closed` that the subscription ended
+    pub async fn recv(&mut self) -> Result<SubscriptionMessage, RecvError> {
+        self.receiver.recv().await
+    }
+}
+
+impl Clone for SubscriptionReceiver {
+    fn clone(&self) -> Self {
+        Self {
+            subscription: self.subscription.clone(),
+            receiver: self.receiver.resubscribe(),
+        }
+    }
+}
+
+impl Debug for SubscriptionReceiver {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SubscriptionReceiver")
+            .field("subscription", &self.subscription)
+            .finish()
+    }
+}
+
+pub struct TypedSubscription<T> {
+    subscription: Subscription,
+    _payload: PhantomData<fn() -> T>,
+}
+
+impl<T: SubscriptionPayload> TypedSubscription<T> {
+    pub(crate) fn new(subscription: Subscription) -> Self {
+        Self {
+            subscription,
+            _payload: PhantomData,
+        }
+    }
+
+    pub fn subscription(&self) -> &Subscription {
+        &self.subscription
+    }
+
+    pub fn into_inner(self) -> Subscription {
+        self.subscription
+    }
+
+    pub async fn next(&self) -> Option<SubscriptionMessage<T>> {
+        self.subscription
+            .next()
+            .await
+            .map(|message| message.into_payload())
+    }
+}
+
+impl<T> Clone for TypedSubscription<T> {
+    fn clone(&self) -> Self {
+        Self {
+            subscription: self.subscription.clone(),
+            _payload: PhantomData,
+        }
+    }
+}
+
+impl<T: SubscriptionPayload> Stream for TypedSubscription<T> {
+    type Item = SubscriptionMessage<T>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.subscription)
+            .poll_next(cx)
+            .map(|message| message.map(|message| message.into_payload()))
+    }
+}
+
+impl<T> Debug for TypedSubscription<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TypedSubscription")
+            .field("subscription", &self.subscription)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_util::StreamExt;
+    use serde_json::json;
+
+    use std::{sync::Arc, time::Duration};
+
+    use tokio::sync::broadcast::error::{RecvError, TryRecvError};
+
+    use super::{OverflowPolicy, QueueLimit, SubscriptionState};
+    use crate::{
+        socket::{ConnectionEvent, ConnectionState, Socket},
+        test_util::{
+            block_on, block_on_paused, config, ok, subscription_kit, subscriptions, MockClient,
+            MockSocket,
+        },
+        Kit, Object, SubscriptionEvent, SubscriptionJournal, SubscriptionModel, Value,
+    };
+
+    const NATIONS: &str = "https://test/subscribe/nation/update";
+
+    #[test]
+    fn rejected_subscribes_fail_with_the_reason() {
+        let (kit, _, socket) = subscription_kit();
+        socket.reject(NATIONS, "forbidden (status 403)");
+        block_on_paused(async {
+            let started = tokio::time::Instant::now();
+            let err = kit
+                .subscribe(SubscriptionModel::Nation, SubscriptionEvent::Update)
+                .await
+                .unwrap_err();
+            assert!(err.contains("forbidden (status 403)"), "{}", err);
+            // no waiting for the timeout
+            assert_eq!(started.elapsed(), Duration::ZERO);
+            // the channel is requested again once in case it expired
+            assert_eq!(socket.subscribed(), vec![NATIONS, NATIONS]);
+            assert!(socket.routed().is_empty());
+        });
+    }
+
+    #[test]
+    fn unanswered_subscribes_time_out() {
+        let client = MockClient::new(|request| {
+            subscriptions(request).unwrap_or_else(|| Err("unexpected request".into()))
+        });
+        let socket = MockSocket::new("1.1");
+        socket.ignore(NATIONS);
+        let kit = Kit::new(
+            config(client)
+                .set_socket(Box::new(socket.clone()))
+                .set_subscribe_timeout(Duration::from_secs(30)),
+        );
+        block_on_paused(async {
+            let started = tokio::time::Instant::now();
+            let err = kit
+                .subscribe(SubscriptionModel::Nation, SubscriptionEvent::Update)
+                .await
+                .unwrap_err();
+            assert!(err.contains("timed out"), "{}", err);
+            assert!(started.elapsed() >= Duration::from_secs(30));
+            assert!(socket.routed().is_empty());
+        });
+    }
+
+    #[test]
+    fn subscribes_stop_waiting_for_a_connection() {
+        let client = MockClient::new(|request| {
+            subscriptions(request).unwrap_or_else(|| Err("unexpected request".into()))
+        });
+        let socket = MockSocket::new("1.1");
+        socket.stall();
+        let kit = Kit::new(
+            config(client)
+                .set_socket(Box::new(socket.clone()))
+                .set_subscribe_timeout(Duration::from_secs(30)),
+        );
+        block_on_paused(async {
+            let started = tokio::time::Instant::now();
+            let err = kit
+                .subscribe(SubscriptionModel::Nation, SubscriptionEvent::Update)
+                .await
+                .unwrap_err();
+            assert!(
+                err.contains("timed out waiting for the connection"),
+                "{}",
+                err
+            );
+            assert!(started.elapsed() >= Duration::from_secs(30));
+            assert!(socket.subscribed().is_empty());
+        });
+    }
+
+    #[test]
+    fn subscribes_fail_with_the_socket() {
+        let client = MockClient::new(|request| {
+            subscriptions(request).unwrap_or_else(|| Err("unexpected request".into()))
+        });
+        let socket = MockSocket::new("1.1");
+        socket.stall();
+        let kit = Kit::new(config(client).set_socket(Box::new(socket.clone())));
+        block_on_paused(async {
+            let started = tokio::time::Instant::now();
+            let subscribing = tokio::spawn({
+                let kit = kit.clone();
+                async move {
+                    kit.subscribe(SubscriptionModel::Nation, SubscriptionEvent::Update)
+                        .await
+                }
+            });
+            while socket.get_state().await != ConnectionState::Connecting {
+                tokio::task::yield_now().await;
+            }
+            socket.fail("app disabled");
+            let err = subscribing.await.unwrap().unwrap_err();
+            assert_eq!(err, "socket failed: app disabled");
+            // without waiting for the subscribe timeout
+            assert_eq!(started.elapsed(), Duration::ZERO);
+
+            let err = kit
+                .subscribe(SubscriptionModel::Alliance, SubscriptionEvent::Update)
+                .await
+                .unwrap_err();
+            assert_eq!(err, "socket failed: app disabled");
+            // nor are frames queued that won't be sent
+            assert!(socket.send("{}".into()).await.is_err());
+        });
+    }
+
+    #[test]
+    fn stream_yields_delivered_messages() {
+        let (kit, _, socket) = subscription_kit();
+        block_on(async {
+            let mut subscription = kit
+                .subscribe(SubscriptionModel::Nation, SubscriptionEvent::Update)
+                .await
+                .unwrap();
+            assert_eq!(socket.subscribed(), vec![NATIONS]);
+            assert_eq!(subscription.channel().await, NATIONS);
+            socket
+                .deliver(NATIONS, "NATION_UPDATE", json!({ "id": 1 }))
+                .await
+                .unwrap();
+            socket
+                .deliver(NATIONS, "NATION_UPDATE", json!({ "id": 2 }))
+                .await
+                .unwrap();
+            let first = subscription.next().await.unwrap();
+            let second = StreamExt::next(&mut subscription).await.unwrap();
+            assert_eq!(first.data.get("id").unwrap().as_i64(), Some(1));
+            assert_eq!(second.data.get("id").unwrap().as_i64(), Some(2));
+        });
+    }
+
+    #[test]
+    fn unsubscribing_ends_the_stream() {
+        let (kit, _, socket) = subscription_kit();
+        block_on(async {
+            let mut subscription = kit
+                .subscribe(SubscriptionModel::Nation, SubscriptionEvent::Update)
+                .await
+                .unwrap();
+            socket
+                .deliver(NATIONS, "NATION_UPDATE", json!({ "id": 1 }))
+                .await
+                .unwrap();
+            kit.unsubscribe(&subscription).await.unwrap();
+            assert!(socket.routed().is_empty());
+            let unsubscribe = socket.sent().pop().unwrap();
+            assert_eq!(unsubscribe["event"], "pusher:unsubscribe");
+            assert_eq!(unsubscribe["data"]["channel"], NATIONS);
+            // what was queued before is still delivered
+            assert!(StreamExt::next(&mut subscription).await.is_some());
+            assert!(StreamExt::next(&mut subscription).await.is_none());
+            // unsubscribing twice does nothing
+            kit.unsubscribe(&subscription).await.unwrap();
+            assert_eq!(
+                socket
+                    .sent()
+                    .iter()
+                    .filter(|f| f["event"] == "pusher:unsubscribe")
+                    .count(),
+                1
+            );
+        });
+    }
+
+    #[test]
+    fn dropping_the_last_clone_unsubscribes() {
+        let (kit, _, socket) = subscription_kit();
+        block_on(async {
+            let subscription = kit
+                .subscribe(SubscriptionModel::Nation, SubscriptionEvent::Update)
+                .await
+                .unwrap();
+            let clone = subscription.clone();
+            drop(subscription);
+            tokio::task::yield_now().await;
+            assert_eq!(socket.routed(), vec![NATIONS]);
+            drop(clone);
+            tokio::task::yield_now().await;
+            assert!(socket.routed().is_empty());
+            assert_eq!(socket.sent().pop().unwrap()["event"], "pusher:unsubscribe");
+        });
+    }
+
+    fn state() -> SubscriptionState {
+        SubscriptionState::new(
+            SubscriptionModel::Nation,
+            SubscriptionEvent::Update,
+            Object::new(),
+            NATIONS.into(),
+        )
+    }
+
+    // the payloads of an event like the protocol hands them over
+    fn objects(value: serde_json::Value) -> Vec<Object> {
+        match serde_json::from_value::<Value>(value).unwrap() {
+            Value::Array(values) => values.iter().map(|v| v.as_object().unwrap()).collect(),
+            value => vec![value.as_object().unwrap()],
+        }
+    }
+
+    #[test]
+    fn messages_carry_the_event_metadata() {
+        let state = state();
+        block_on(async {
+            state
+                .push_event(
+                    "NATION_UPDATE",
+                    NATIONS.into(),
+                    10,
+                    false,
+                    objects(json!({ "id": 1 })),
+                )
+                .await
+                .unwrap();
+            state
+                .push_event(
+                    "NATION_UPDATE",
+                    NATIONS.into(),
+                    11,
+                    true,
+                    objects(json!([{ "id": 2 }, { "id": 3 }])),
+                )
+                .await
+                .unwrap();
+            let single = state.queue.pop().await.unwrap();
+            assert_eq!(single.model, SubscriptionModel::Nation);
+            assert_eq!(single.event, "NATION_UPDATE");
+            assert!(!single.bulk);
+            assert!(!single.backfill);
+            assert_eq!(single.channel, NATIONS);
+            assert_eq!(single.received, 10);
+            assert_eq!(single.journal_id, None);
+            for id in [2, 3] {
+                let message = state.queue.pop().await.unwrap();
+                assert_eq!(message.event, "NATION_UPDATE");
+                assert!(message.bulk);
+                assert_eq!(message.received, 11);
+                assert_eq!(message.data.get("id").unwrap().as_i64(), Some(id));
+            }
+        });
+    }
+
+    #[test]
+    fn filters_are_part_of_the_channel() {
+        let (kit, _, socket) = subscription_kit();
+        block_on(async {
+            let filters = Object::new();
+            filters.insert("alliance_id".into(), Value::String("1,2".into()));
+            let subscription = kit
+                .subscribe_with_filters(
+                    SubscriptionModel::Nation,
+                    SubscriptionEvent::Update,
+                    filters,
+                )
+                .await
+                .unwrap();
+            assert_eq!(
+                subscription.channel().await,
+                "https://test/subscribe/nation/update?alliance_id=1%2C2"
+            );
+            assert_eq!(socket.subscribed().len(), 1);
+        });
+    }
+
+    #[test]
+    fn updating_filters_moves_channels() {
+        let (kit, _, socket) = subscription_kit();
+        block_on(async {
+            let subscription = kit
+                .subscribe(SubscriptionModel::Nation, SubscriptionEvent::Update)
+                .await
+                .unwrap();
+            let filters = Object::new();
+            filters.insert("alliance_id".into(), Value::Int(1));
+            subscription.update_filters(filters).await.unwrap();
+
+            let moved = "https://test/subscribe/nation/update?alliance_id=1";
+            assert_eq!(subscription.channel().await, moved);
+            assert_eq!(socket.subscribed(), vec![NATIONS, moved]);
+            assert_eq!(socket.routed(), vec![moved]);
+            let unsubscribe = socket.sent().pop().unwrap();
+            assert_eq!(unsubscribe["event"], "pusher:unsubscribe");
+            assert_eq!(unsubscribe["data"]["channel"], NATIONS);
+        });
+    }
+
+    // hands out a new channel every time one is requested, city channels
+    // can only be requested once
+    fn expiring_kit(lifetime: Duration) -> (Kit, MockSocket) {
+        let requested = Arc::new(std::sync::Mutex::new(Vec::<String>::new()));
+        let client = MockClient::new(move |request| {
+            if !request.url.starts_with("https://test/subscribe/") {
+                return subscriptions(request).unwrap_or_else(|| Err("unexpected request".into()));
+            }
+            let mut requested = requested.lock().unwrap();
+            requested.push(request.url.clone());
+            let count = requested.iter().filter(|url| **url == request.url).count();
+            if request.url.contains("/city/") && count > 1 {
+                return Err("no more channels".into());
+            }
+            ok(json!({ "channel": format!("{}#{}", request.url, count) }))
+        });
+        let socket = MockSocket::new("1.1");
+        let kit = Kit::new(
+            config(client)
+                .set_socket(Box::new(socket.clone()))
+                .set_channel_lifetime(Some(lifetime)),
+        );
+        (kit, socket)
+    }
+
+    #[test]
+    fn channels_are_reauthorized_before_they_expire() {
+        let (kit, socket) = expiring_kit(Duration::from_secs(600));
+        block_on_paused(async {
+            let subscription = kit
+                .subscribe(SubscriptionModel::Nation, SubscriptionEvent::Update)
+                .await
+                .unwrap();
+            let first = format!("{}#1", NATIONS);
+            assert_eq!(socket.subscribed(), vec![first.clone()]);
+
+            // reauthorized a tenth of the lifetime early
+            tokio::time::sleep(Duration::from_secs(539)).await;
+            assert_eq!(socket.subscribed(), vec![first.clone()]);
+            tokio::time::sleep(Duration::from_secs(2)).await;
+            let second = format!("{}#2", NATIONS);
+            assert_eq!(socket.subscribed(), vec![first.clone(), second.clone()]);
+            assert_eq!(subscription.channel().await, second);
+            assert_eq!(socket.routed(), vec![second.clone()]);
+            let unsubscribe = socket.sent().pop().unwrap();
+            assert_eq!(unsubscribe["event"], "pusher:unsubscribe");
+            assert_eq!(unsubscribe["data"]["channel"], first);
+
+            socket
+                .deliver(&second, "NATION_UPDATE", json!({ "id": 1 }))
+                .await
+                .unwrap();
+            let message = subscription.next().await.unwrap();
+            assert_eq!(message.data.get("id").unwrap().as_i64(), Some(1));
+
+            // and again before the new channel expires
+            tokio::time::sleep(Duration::from_secs(540)).await;
+            assert_eq!(socket.subscribed().len(), 3);
+        });
+    }
+
+    #[test]
+    fn failed_reauthorizations_close_only_that_subscription() {
+        let (kit, socket) = expiring_kit(Duration::from_secs(600));
+        block_on_paused(async {
+            let started = tokio::time::Instant::now();
+            let mut events = kit.connection_events();
+            let cities = kit
+                .subscribe(SubscriptionModel::City, SubscriptionEvent::Update)
+                .await
+                .unwrap();
+            let nations = kit
+                .subscribe(SubscriptionModel::Nation, SubscriptionEvent::Update)
+                .await
+                .unwrap();
+            let city_channel = cities.channel().await;
+
+            // retried until the channel expires
+            let retried = events.recv().await.unwrap();
+            assert!(matches!(
+                retried,
+                ConnectionEvent::Error(reason) if reason.contains("no more channels")
+            ));
+            assert_eq!(started.elapsed(), Duration::from_secs(540));
+            let failed = loop {
+                if let ConnectionEvent::SubscriptionFailed { channel, reason } =
+                    events.recv().await.unwrap()
+                {
+                    break (channel, reason);
+                }
+            };
+            assert_eq!(failed, (city_channel, "no more channels".to_string()));
+            assert_eq!(started.elapsed(), Duration::from_secs(600));
+            assert!(cities.next().await.is_none());
+
+            // the nation subscription kept going
+            let nation_channel = nations.channel().await;
+            assert_eq!(nation_channel, format!("{}#2", NATIONS));
+            assert_eq!(socket.routed(), vec![nation_channel]);
+        });
+    }
+
+    #[test]
+    fn only_events_from_both_channels_are_deduplicated() {
+        let state = state();
+        let moved = "https://test/subscribe/nation/update?alliance_id=1";
+        block_on(async {
+            state.begin_switch(moved.into()).await;
+            for channel in [NATIONS, moved] {
+                for id in [1, 1, 2] {
+                    state
+                        .push_event(
+                            "NATION_UPDATE",
+                            channel.into(),
+                            0,
+                            false,
+                            objects(json!({ "id": id })),
+                        )
+                        .await
+                        .unwrap();
+                }
+            }
+            // a different event for the same record isn't the same message
+            state
+                .push_event(
+                    "NATION_DELETE",
+                    moved.into(),
+                    0,
+                    false,
+                    objects(json!({ "id": 2 })),
+                )
+                .await
+                .unwrap();
+            assert_eq!(queued(&state).await, vec![1, 1, 2, 2]);
+
+            state.end_dedupe();
+            state
+                .push_event(
+                    "NATION_UPDATE",
+                    moved.into(),
+                    0,
+                    false,
+                    objects(json!({ "id": 1 })),
+                )
+                .await
+                .unwrap();
+            assert_eq!(queued(&state).await, vec![1]);
+        });
+    }
+
+    #[test]
+    fn unsubscribing_while_moving_leaves_both_channels() {
+        let (kit, _, socket) = subscription_kit();
+        let moved = "https://test/subscribe/nation/update?alliance_id=1";
+        block_on(async {
+            let subscription = kit
+                .subscribe(SubscriptionModel::Nation, SubscriptionEvent::Update)
+                .await
+                .unwrap();
+            let state = subscription.handle.state.clone();
+            state.begin_switch(moved.into()).await;
+            socket
+                .add_subscription_channel(moved.into(), state.clone())
+                .await;
+            assert_eq!(socket.routed(), vec![NATIONS, moved]);
+
+            kit.unsubscribe(&subscription).await.unwrap();
+            assert!(socket.routed().is_empty());
+            let unsubscribed = socket
+                .sent()
+                .iter()
+                .filter(|f| f["event"] == "pusher:unsubscribe")
+                .map(|f| f["data"]["channel"].as_str().unwrap().to_string())
+                .collect::<Vec<_>>();
+            assert_eq!(unsubscribed, vec![NATIONS, moved]);
+            // the switch doesn't wait for a reply that won't be routed
+            assert_eq!(
+                state.wait_switch().await.unwrap_err(),
+                "subscription was unsubscribed"
+            );
+        });
+    }
+
+    #[test]
+    fn receivers_get_every_message_instead_of_next() {
+        let (kit, _, socket) = subscription_kit();
+        block_on(async {
+            let subscription = kit
+                .subscribe(SubscriptionModel::Nation, SubscriptionEvent::Update)
+                .await
+                .unwrap();
+            let state = subscription.state().clone();
+            socket
+                .deliver(NATIONS, "NATION_UPDATE", json!({ "id": 1 }))
+                .await
+                .unwrap();
+            let mut first = subscription.subscribe_receiver().await;
+            let mut second = first.clone();
+            let reading = tokio::spawn(async move {
+                let mut ids = Vec::new();
+                for _ in 0..3 {
+                    let message = first.recv().await.unwrap();
+                    ids.push(message.data.get("id").unwrap().as_i64().unwrap());
+                }
+                ids
+            });
+            for id in 2..=3 {
+                socket
+                    .deliver(NATIONS, "NATION_UPDATE", json!({ "id": id }))
+                    .await
+                    .unwrap();
+            }
+            // the message queued before is handed to the first receiver
+            assert_eq!(reading.await.unwrap(), vec![1, 2, 3]);
+            for id in 2..=3 {
+                let message = second.recv().await.unwrap();
+                assert_eq!(message.data.get("id").unwrap().as_i64(), Some(id));
+            }
+            // nothing piles up where no one reads it
+            assert!(state.queue.is_empty().await);
+        });
+    }
+
+    #[test]
+    fn dropped_messages_arent_fanned_out() {
+        let state = limited(OverflowPolicy::DropNewest);
+        block_on(async {
+            let mut receiver = state.queue.subscribe();
+            for id in 1..=3 {
+                push(&state, id).await.unwrap();
+            }
+            assert_eq!(queued(&state).await, vec![1, 2]);
+            for id in 1..=2 {
+                let message = receiver.recv().await.unwrap();
+                assert_eq!(message.data.get("id").unwrap().as_i64(), Some(id));
+            }
+            assert!(matches!(receiver.try_recv(), Err(TryRecvError::Empty)));
+        });
+    }
+
+    #[test]
+    fn receivers_close_with_the_subscription() {
+        let (kit, _, _) = subscription_kit();
+        block_on(async {
+            let subscription = kit
+                .subscribe(SubscriptionModel::Nation, SubscriptionEvent::Update)
+                .await
+                .unwrap();
+            let mut receiver = subscription.subscribe_receiver().await;
+            kit.unsubscribe(receiver.subscription()).await.unwrap();
+            assert!(matches!(receiver.recv().await, Err(RecvError::Closed)));
+        });
+    }
+
+    fn limited(overflow: OverflowPolicy) -> SubscriptionState {
+        state().set_queue_limit(Some(QueueLimit::new(2, overflow)))
+    }
+
+    async fn push(state: &SubscriptionState, id: i64) -> Result<(), String> {
+        state
+            .push_event(
+                "NATION_UPDATE",
+                NATIONS.into(),
+                0,
+                false,
+                objects(json!({ "id": id })),
+            )
+            .await
+    }
+
+    async fn queued(state: &SubscriptionState) -> Vec<i64> {
+        let mut ids = Vec::new();
+        while !state.queue.is_empty().await {
+            let message = state.queue.pop().await.unwrap();
+            ids.push(message.data.get("id").unwrap().as_i64().unwrap());
+        }
+        ids
+    }
+
+    #[test]
+    fn overflowing_drops_the_oldest() {
+        let state = limited(OverflowPolicy::DropOldest);
+        block_on(async {
+            for id in 1..=4 {
+                push(&state, id).await.unwrap();
+            }
+            assert_eq!(state.queue.dropped(), 2);
+            assert_eq!(queued(&state).await, vec![3, 4]);
+            assert_eq!(state.watermark(), Some(4));
+        });
+    }
+
+    #[test]
+    fn journals_are_replayed_and_acknowledged() {
+        let path = std::env::temp_dir().join(format!(
+            "pnwkit-subscription-journal-{}.jsonl",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        block_on(async {
+            let state = state().set_journal(Some(SubscriptionJournal::open(&path).unwrap()));
+            for id in 1..=3 {
+                push(&state, id).await.unwrap();
+            }
+            assert_eq!(state.journal().unwrap().len(), 3);
+
+            // what was journaled is queued even past the limit
+            let state = limited(OverflowPolicy::DropNewest)
+                .set_journal(Some(SubscriptionJournal::open(&path).unwrap()));
+            state.replay().await;
+            assert_eq!(state.queue.dropped(), 0);
+            assert_eq!(queued(&state).await, vec![1, 2, 3]);
+        });
+
+        let (kit, _, socket) = subscription_kit();
+        block_on(async {
+            let subscription = kit
+                .subscribe_with_journal(
+                    SubscriptionModel::Nation,
+                    SubscriptionEvent::Update,
+                    Object::new(),
+                    SubscriptionJournal::open(&path).unwrap(),
+                )
+                .await
+                .unwrap();
+            socket
+                .deliver(NATIONS, "NATION_UPDATE", json!({ "id": 4 }))
+                .await
+                .unwrap();
+            let first = subscription.next().await.unwrap();
+            assert_eq!(first.journal_id, Some(1));
+            subscription.ack(1).await.unwrap();
+            let ids = SubscriptionJournal::open(&path)
+                .unwrap()
+                .unacked()
+                .iter()
+                .map(|m| m.journal_id.unwrap())
+                .collect::<Vec<_>>();
+            assert_eq!(ids, vec![2, 3, 4]);
+        });
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn dropped_messages_arent_journaled() {
+        let path = std::env::temp_dir().join(format!(
+            "pnwkit-subscription-dropped-{}.jsonl",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        block_on(async {
+            let journaled = limited(OverflowPolicy::DropNewest)
+                .set_journal(Some(SubscriptionJournal::open(&path).unwrap()));
+            for id in 1..=4 {
+                push(&journaled, id).await.unwrap();
+            }
+            assert_eq!(journaled.queue.dropped(), 2);
+            assert_eq!(journaled.journal().unwrap().len(), 2);
+            journaled.queue.close();
+            push(&journaled, 5).await.unwrap();
+            assert_eq!(journaled.journal().unwrap().len(), 2);
+
+            // only what was queued comes back after a restart
+            let restarted = state().set_journal(Some(SubscriptionJournal::open(&path).unwrap()));
+            restarted.replay().await;
+            assert_eq!(queued(&restarted).await, vec![1, 2]);
+        });
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn overflowing_drops_the_newest() {
+        let state = limited(OverflowPolicy::DropNewest);
+        block_on(async {
+            for id in 1..=4 {
+                push(&state, id).await.unwrap();
+            }
+            assert_eq!(state.queue.dropped(), 2);
+            assert_eq!(queued(&state).await, vec![1, 2]);
+            // the dropped records can still be backfilled
+            assert_eq!(state.watermark(), Some(2));
+        });
+    }
+
+    #[test]
+    fn overflowing_disconnects() {
+        let state = limited(OverflowPolicy::Disconnect);
+        block_on(async {
+            push(&state, 1).await.unwrap();
+            push(&state, 2).await.unwrap();
+            let err = push(&state, 3).await.unwrap_err();
+            assert_eq!(err, "subscription queue overflowed");
+            assert!(state.queue.is_closed());
+            assert_eq!(state.watermark(), Some(2));
+            // what was queued can still be popped
+            assert_eq!(queued(&state).await, vec![1, 2]);
+            assert!(state.queue.pop().await.is_none());
+        });
+    }
+
+    #[test]
+    fn overflowing_blocks_until_popped() {
+        let state = Arc::new(limited(OverflowPolicy::Block));
+        block_on_paused(async {
+            push(&state, 1).await.unwrap();
+            push(&state, 2).await.unwrap();
+            let pushing = tokio::spawn({
+                let state = state.clone();
+                async move { push(&state, 3).await }
+            });
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            assert!(!pushing.is_finished());
+            assert_eq!(state.watermark(), Some(2));
+            assert_eq!(
+                state
+                    .queue
+                    .pop()
+                    .await
+                    .unwrap()
+                    .data
+                    .get("id")
+                    .unwrap()
+                    .as_i64(),
+                Some(1)
+            );
+            pushing.await.unwrap().unwrap();
+            assert_eq!(state.queue.dropped(), 0);
+            assert_eq!(queued(&state).await, vec![2, 3]);
+            assert_eq!(state.watermark(), Some(3));
+        });
+    }
+
+    #[cfg(feature = "sync")]
+    mod blocking {
+        use serde_json::json;
+
+        use super::NATIONS;
+        use crate::{
+            test_util::{block_on, subscription_kit},
+            SubscriptionEvent, SubscriptionModel,
+        };
+
+        #[test]
+        fn messages_are_received_without_a_runtime() {
+            let (kit, _, socket) = subscription_kit();
+            let subscription = kit
+                .subscribe_sync(SubscriptionModel::Nation, SubscriptionEvent::Update)
+                .unwrap();
+            assert_eq!(socket.subscribed(), vec![NATIONS]);
+            for id in [1, 2] {
+                block_on(socket.deliver(NATIONS, "NATION_UPDATE", json!({ "id": id }))).unwrap();
+            }
+            let first = subscription.recv_blocking().unwrap().unwrap();
+            assert_eq!(first.data.get("id").unwrap().as_i64(), Some(1));
+
+            kit.unsubscribe_sync(&subscription).unwrap();
+            // what was queued before is still delivered, then it ends
+            let rest = subscription
+                .iter_blocking()
+                .map(|message| message.unwrap().data.get("id").unwrap().as_i64())
+                .collect::<Vec<_>>();
+            assert_eq!(rest, vec![Some(2)]);
+            assert!(matches!(subscription.recv_blocking(), Ok(None)));
+        }
+
+        #[test]
+        fn subscriptions_are_iterators() {
+            let (kit, _, socket) = subscription_kit();
+            let mut subscription = kit
+                .subscribe_sync(SubscriptionModel::Nation, SubscriptionEvent::Update)
+                .unwrap();
+            for id in [1, 2] {
+                block_on(socket.deliver(NATIONS, "NATION_UPDATE", json!({ "id": id }))).unwrap();
+            }
+            kit.unsubscribe_sync(&subscription).unwrap();
+            let ids = subscription
+                .by_ref()
+                .map(|message| message.data.get("id").unwrap().as_i64())
+                .collect::<Vec<_>>();
+            assert_eq!(ids, vec![Some(1), Some(2)]);
+        }
+
+        #[test]
+        fn blocking_inside_a_runtime_is_an_error() {
+            let (kit, _, _) = subscription_kit();
+            let subscription = kit
+                .subscribe_sync(SubscriptionModel::Nation, SubscriptionEvent::Update)
+                .unwrap();
+            block_on(async {
+                let err = kit
+                    .subscribe_sync(SubscriptionModel::City, SubscriptionEvent::Update)
+                    .unwrap_err();
+                assert!(err.contains("inside a runtime"), "{}", err);
+                assert!(subscription.recv_blocking().is_err());
+                let mut messages = subscription.iter_blocking();
+                assert!(matches!(messages.next(), Some(Err(_))));
+                assert!(messages.next().is_none());
+            });
+        }
+    }
+
+    #[cfg(feature = "async")]
+    mod backfill {
+        use std::sync::Arc;
+
+        use serde_json::json;
+
+        use crate::{
+            request::{Request, ResponseResult},
+            test_util::{
+                block_on, body, config, ok, page, page_number, subscriptions, MockClient,
+                MockSocket,
+            },
+            Kit, Subscription, SubscriptionEvent, SubscriptionModel, SubscriptionState,
+        };
+
+        const CREATED: &str = "https://test/subscribe/nation/create";
+
+        // answers the subscription endpoints, the schema of nations and the
+        // backfill query with `records`
+        fn backfill_kit(
+            records: impl Fn(&Request) -> ResponseResult + Send + Sync + 'static,
+        ) -> (Kit, MockClient, MockSocket) {
+            let client = MockClient::new(move |request| {
+                if let Some(response) = subscriptions(request) {
+                    return response;
+                }
+                if request
+                    .body
+                    .as_deref()
+                    .unwrap_or_default()
+                    .contains("__type(")
+                {
+                    return ok(json!({ "data": { "__type": { "fields": [
+                        { "name": "id" },
+                        { "name": "nation_name" },
+                    ] } } }));
+                }
+                records(request)
+            });
+            let socket = MockSocket::new("1.1");
+            let kit = Kit::new(config(client.clone()).set_socket(Box::new(socket.clone())));
+            (kit, client, socket)
+        }
+
+        async fn ids(state: &SubscriptionState) -> Vec<i64> {
+            let mut ids = Vec::new();
+            while !state.queue.is_empty().await {
+                let message = state.queue.pop().await.unwrap();
+                ids.push(message.data.get("id").unwrap().as_i64().unwrap());
+            }
+            ids
+        }
+
+        // the subscription is kept, dropping it would unsubscribe
+        fn state(kit: &Kit, event: SubscriptionEvent) -> (Subscription, Arc<SubscriptionState>) {
+            let subscription = block_on(kit.subscribe(SubscriptionModel::Nation, event)).unwrap();
+            let state = subscription.handle.state.clone();
+            (subscription, state)
+        }
+
+        #[test]
+        fn backfill_skips_records_that_already_arrived() {
+            let (kit, client, socket) = backfill_kit(|request| {
+                let body = request.body.as_deref().unwrap();
+                if body.contains("\"__page\":1") {
+                    page(vec![json!({ "id": 6 }), json!({ "id": 7 })], 1, true)
+                } else {
+                    page(vec![json!({ "id": 8 })], 2, false)
+                }
+            });
+            let (_subscription, state) = state(&kit, SubscriptionEvent::Create);
+            block_on(async {
+                socket
+                    .deliver(CREATED, "NATION_CREATE", json!({ "id": 5 }))
+                    .await
+                    .unwrap();
+                assert_eq!(ids(&state).await, vec![5]);
+                kit.subscribe_request(state.clone()).await.unwrap();
+                // arrived after resubscribing, before the backfill finished
+                socket
+                    .deliver(CREATED, "NATION_CREATE", json!({ "id": 7 }))
+                    .await
+                    .unwrap();
+                kit.backfill(&state).await.unwrap();
+                socket
+                    .deliver(CREATED, "NATION_CREATE", json!({ "id": 8 }))
+                    .await
+                    .unwrap();
+
+                let message = state.queue.pop().await.unwrap();
+                assert!(!message.backfill);
+                assert_eq!(ids(&state).await, vec![6, 8]);
+            });
+
+            let queries = client
+                .bodies()
+                .iter()
+                .map(|body| body["query"].as_str().unwrap().to_string())
+                .collect::<Vec<_>>();
+            assert_eq!(queries.len(), 3);
+            assert!(queries[0].contains("__type(name: \"Nation\")"));
+            assert!(queries[1].contains("min_id: 6"));
+            assert!(queries[1].contains("nation_name"));
+            // not in the schema
+            assert!(!queries[1].contains("leader_name"));
+        }
+
+        #[test]
+        fn backfill_is_bounded() {
+            let (kit, client, socket) = backfill_kit(|request| {
+                let page_number = page_number(&body(request));
+                page(vec![json!({ "id": page_number + 1 })], page_number, true)
+            });
+            let (_subscription, state) = state(&kit, SubscriptionEvent::Create);
+            block_on(async {
+                socket
+                    .deliver(CREATED, "NATION_CREATE", json!({ "id": 1 }))
+                    .await
+                    .unwrap();
+                ids(&state).await;
+                kit.subscribe_request(state.clone()).await.unwrap();
+                let err = kit.backfill(&state).await.unwrap_err();
+                assert_eq!(err, "backfill stopped after 10 pages");
+                assert_eq!(ids(&state).await, (2..12).collect::<Vec<_>>());
+            });
+            // the schema and ten pages
+            assert_eq!(client.bodies().len(), 11);
+        }
+
+        #[test]
+        fn only_creations_are_backfilled() {
+            let (kit, client, _) = backfill_kit(|_| Err("unexpected request".into()));
+            let (_subscription, state) = state(&kit, SubscriptionEvent::Update);
+            let err = block_on(kit.backfill(&state)).unwrap_err();
+            assert_eq!(err, "backfilling update events isn't supported");
+            assert!(client.bodies().is_empty());
+        }
+    }
+}
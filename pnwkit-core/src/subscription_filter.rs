@@ -0,0 +1,253 @@
+use crate::{Object, SubscriptionEvent, SubscriptionModel, Value};
+
+#[derive(Clone, Debug)]
+pub struct SubscriptionFilter {
+    model: SubscriptionModel,
+    filters: Vec<(String, Vec<Value>)>,
+}
+
+impl SubscriptionFilter {
+    pub fn new(model: SubscriptionModel) -> Self {
+        Self {
+            model,
+            filters: Vec::new(),
+        }
+    }
+
+    // filters given as an object take a number, string or bool or an array
+    // of them, strings are passed on as they are so they can hold several
+    // comma separated values, they are checked like `validate` does
+    pub fn from_object(model: SubscriptionModel, filters: &Object) -> Result<Self, String> {
+        let mut filters = filters
+            .iter()
+            .map(|i| {
+                let (name, value) = i.pair();
+                let values = match value {
+                    Value::Array(values) => values.clone(),
+                    value => vec![value.clone()],
+                };
+                (name.clone(), values)
+            })
+            .collect::<Vec<_>>();
+        // objects don't keep their order, sorting keeps the url the same
+        filters.sort_by(|a, b| a.0.cmp(&b.0));
+        let filter = Self { model, filters };
+        filter.validate()?;
+        Ok(filter)
+    }
+
+    pub fn model(&self) -> &SubscriptionModel {
+        &self.model
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.filters.is_empty()
+    }
+
+    // setting a filter again adds to its values
+    pub fn set_filter<V: Into<Value>>(
+        mut self,
+        name: &str,
+        values: impl IntoIterator<Item = V>,
+    ) -> Self {
+        let values = values.into_iter().map(Into::into);
+        match self.filters.iter_mut().find(|(n, _)| n == name) {
+            Some((_, existing)) => existing.extend(values),
+            None => self.filters.push((name.into(), values.collect())),
+        }
+        self
+    }
+
+    pub fn set_id(self, ids: impl IntoIterator<Item = i64>) -> Self {
+        self.set_filter("id", ids)
+    }
+
+    pub fn set_alliance_id(self, ids: impl IntoIterator<Item = i64>) -> Self {
+        self.set_filter("alliance_id", ids)
+    }
+
+    pub fn set_nation_id(self, ids: impl IntoIterator<Item = i64>) -> Self {
+        self.set_filter("nation_id", ids)
+    }
+
+    pub fn set_war_id(self, ids: impl IntoIterator<Item = i64>) -> Self {
+        self.set_filter("war_id", ids)
+    }
+
+    pub fn set_att_id(self, ids: impl IntoIterator<Item = i64>) -> Self {
+        self.set_filter("att_id", ids)
+    }
+
+    pub fn set_def_id(self, ids: impl IntoIterator<Item = i64>) -> Self {
+        self.set_filter("def_id", ids)
+    }
+
+    pub fn set_sender_id(self, ids: impl IntoIterator<Item = i64>) -> Self {
+        self.set_filter("sender_id", ids)
+    }
+
+    pub fn set_receiver_id(self, ids: impl IntoIterator<Item = i64>) -> Self {
+        self.set_filter("receiver_id", ids)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        for (name, values) in &self.filters {
+            if !self.model.filters().contains(&name.as_str()) {
+                return Err(format!(
+                    "{} subscriptions can't be filtered by {}",
+                    self.model.to_string(),
+                    name
+                ));
+            }
+            if values.is_empty() {
+                return Err(format!("filter {} has no values", name));
+            }
+            if !values.iter().all(is_filter_value) {
+                return Err(format!(
+                    "filter {} must be a number, string or bool, or an array of them",
+                    name
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    // `subscribe_url` is the `{model}`/`{event}` template from the config
+    pub fn url(&self, subscribe_url: &str, event: &SubscriptionEvent) -> Result<String, String> {
+        self.validate()?;
+        let url = subscribe_url
+            .replace("{model}", &self.model.to_string())
+            .replace("{event}", &event.to_string());
+        if self.filters.is_empty() {
+            return Ok(url);
+        }
+        Ok(format!("{}?{}", url, self.query_string()))
+    }
+
+    pub fn to_object(&self) -> Object {
+        self.filters
+            .iter()
+            .map(|(name, values)| (name.clone(), Value::Array(values.clone())))
+            .collect()
+    }
+
+    fn query_string(&self) -> String {
+        let pairs = self
+            .filters
+            .iter()
+            .map(|(name, values)| {
+                let values = values
+                    .iter()
+                    .map(|v| match v {
+                        Value::Bool(v) => v.to_string(),
+                        Value::Int(v) => v.to_string(),
+                        Value::Float(v) => v.to_string(),
+                        Value::String(v) => v.clone(),
+                        _ => String::new(),
+                    })
+                    .collect::<Vec<_>>()
+                    .join(",");
+                (name.as_str(), values)
+            })
+            .collect::<Vec<_>>();
+        // a list of string pairs always serializes
+        serde_urlencoded::to_string(pairs).unwrap_or_default()
+    }
+}
+
+fn is_filter_value(value: &Value) -> bool {
+    matches!(
+        value,
+        Value::Bool(_) | Value::Int(_) | Value::Float(_) | Value::String(_)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SubscriptionFilter;
+    use crate::{Object, SubscriptionEvent, SubscriptionModel, Value};
+
+    const URL: &str = "https://test/subscribe/{model}/{event}";
+
+    fn object(pairs: Vec<(&str, Value)>) -> Object {
+        pairs.into_iter().map(|(k, v)| (k.to_string(), v)).collect()
+    }
+
+    #[test]
+    fn urls_without_filters() {
+        let url = SubscriptionFilter::new(SubscriptionModel::WarAttack)
+            .url(URL, &SubscriptionEvent::Create)
+            .unwrap();
+        assert_eq!(url, "https://test/subscribe/warattack/create");
+    }
+
+    #[test]
+    fn values_are_joined_and_encoded() {
+        let url = SubscriptionFilter::new(SubscriptionModel::War)
+            .set_att_id([1, 2])
+            .set_alliance_id([3])
+            .set_att_id([4])
+            .url(URL, &SubscriptionEvent::Update)
+            .unwrap();
+        assert_eq!(
+            url,
+            "https://test/subscribe/war/update?att_id=1%2C2%2C4&alliance_id=3"
+        );
+
+        let url = SubscriptionFilter::new(SubscriptionModel::Nation)
+            .set_filter("id", ["1 & 2=3"])
+            .url(URL, &SubscriptionEvent::Update)
+            .unwrap();
+        assert_eq!(url, "https://test/subscribe/nation/update?id=1+%26+2%3D3");
+    }
+
+    #[test]
+    fn objects_take_numbers_strings_and_bools() {
+        let filters = object(vec![
+            ("sender_id", Value::String("1,2".into())),
+            ("id", Value::Array(vec![Value::Int(3), Value::Float(4.5)])),
+            ("receiver_type", Value::Bool(true)),
+        ]);
+        let filter = SubscriptionFilter::from_object(SubscriptionModel::Bankrec, &filters).unwrap();
+        // sorted by name, so the url doesn't depend on the object's order
+        assert_eq!(
+            filter.url(URL, &SubscriptionEvent::Create).unwrap(),
+            "https://test/subscribe/bankrec/create?id=3%2C4.5&receiver_type=true&sender_id=1%2C2"
+        );
+        let object = filter.to_object();
+        assert_eq!(object.get("id").unwrap().as_array().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn filters_are_checked_per_model() {
+        let err = SubscriptionFilter::new(SubscriptionModel::Nation)
+            .set_war_id([1])
+            .url(URL, &SubscriptionEvent::Update)
+            .unwrap_err();
+        assert_eq!(err, "nation subscriptions can't be filtered by war_id");
+        assert!(SubscriptionFilter::new(SubscriptionModel::WarAttack)
+            .set_war_id([1])
+            .validate()
+            .is_ok());
+
+        let filters = object(vec![("color", Value::String("red".into()))]);
+        let err = SubscriptionFilter::from_object(SubscriptionModel::Nation, &filters).unwrap_err();
+        assert_eq!(err, "nation subscriptions can't be filtered by color");
+    }
+
+    #[test]
+    fn filters_need_plain_values() {
+        let err = SubscriptionFilter::new(SubscriptionModel::Nation)
+            .set_id(Vec::new())
+            .validate()
+            .unwrap_err();
+        assert_eq!(err, "filter id has no values");
+
+        let filters = object(vec![("id", Value::Object(Object::new()))]);
+        let err = SubscriptionFilter::from_object(SubscriptionModel::Nation, &filters).unwrap_err();
+        assert_eq!(
+            err,
+            "filter id must be a number, string or bool, or an array of them"
+        );
+    }
+}
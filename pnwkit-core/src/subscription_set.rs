@@ -0,0 +1,256 @@
+use std::{
+    fmt::Debug,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use futures_util::{Stream, StreamExt};
+
+use crate::{
+    Kit, Subscription, SubscriptionEvent, SubscriptionFilter, SubscriptionMessage,
+    SubscriptionModel,
+};
+
+// merges subscriptions into one stream of messages tagged with the key they
+// were added under, each subscription's messages keep their order
+pub struct SubscriptionSet<K = String> {
+    kit: Kit,
+    subscriptions: Vec<(K, Subscription)>,
+    // where the next poll starts so a busy subscription can't starve the rest
+    start: usize,
+}
+
+impl<K: Clone + PartialEq + Unpin> SubscriptionSet<K> {
+    pub(crate) fn new(kit: Kit) -> Self {
+        Self {
+            kit,
+            subscriptions: Vec::new(),
+            start: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.subscriptions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.subscriptions.is_empty()
+    }
+
+    pub fn tags(&self) -> impl Iterator<Item = &K> {
+        self.subscriptions.iter().map(|(tag, _)| tag)
+    }
+
+    pub fn get(&self, tag: &K) -> Option<&Subscription> {
+        self.subscriptions
+            .iter()
+            .find(|(t, _)| t == tag)
+            .map(|(_, subscription)| subscription)
+    }
+
+    // returns the subscription that was under the tag before
+    pub fn insert(&mut self, tag: K, subscription: Subscription) -> Option<Subscription> {
+        match self.subscriptions.iter_mut().find(|(t, _)| *t == tag) {
+            Some((_, existing)) => Some(std::mem::replace(existing, subscription)),
+            None => {
+                self.subscriptions.push((tag, subscription));
+                None
+            },
+        }
+    }
+
+    // the subscription is unsubscribed once it and its clones are dropped
+    pub fn remove(&mut self, tag: &K) -> Option<Subscription> {
+        let index = self.subscriptions.iter().position(|(t, _)| t == tag)?;
+        Some(self.subscriptions.remove(index).1)
+    }
+
+    pub async fn subscribe(
+        &mut self,
+        tag: K,
+        model: SubscriptionModel,
+        event: SubscriptionEvent,
+    ) -> Result<(), String> {
+        let subscription = self.kit.subscribe(model, event).await?;
+        self.insert(tag, subscription);
+        Ok(())
+    }
+
+    pub async fn subscribe_with_filter(
+        &mut self,
+        tag: K,
+        event: SubscriptionEvent,
+        filter: SubscriptionFilter,
+    ) -> Result<(), String> {
+        let subscription = self.kit.subscribe_with_filter(event, filter).await?;
+        self.insert(tag, subscription);
+        Ok(())
+    }
+
+    // returns `None` once every subscription ended or the set is empty
+    pub async fn next(&mut self) -> Option<(K, SubscriptionMessage)> {
+        StreamExt::next(self).await
+    }
+}
+
+impl<K: Clone + PartialEq + Unpin> Stream for SubscriptionSet<K> {
+    type Item = (K, SubscriptionMessage);
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        let mut index = this.start;
+        let mut polled = 0;
+        while polled < this.subscriptions.len() {
+            index %= this.subscriptions.len();
+            let (tag, subscription) = &mut this.subscriptions[index];
+            match Pin::new(subscription).poll_next(cx) {
+                Poll::Ready(Some(message)) => {
+                    this.start = index + 1;
+                    return Poll::Ready(Some((tag.clone(), message)));
+                },
+                // the subscription ended, the next one moves into its place
+                Poll::Ready(None) => {
+                    this.subscriptions.remove(index);
+                },
+                Poll::Pending => {
+                    index += 1;
+                    polled += 1;
+                },
+            }
+        }
+        if this.subscriptions.is_empty() {
+            Poll::Ready(None)
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+impl<K: Debug> Debug for SubscriptionSet<K> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SubscriptionSet")
+            .field("subscriptions", &self.subscriptions)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::{
+        test_util::{block_on, subscription_kit},
+        SubscriptionEvent, SubscriptionFilter, SubscriptionModel,
+    };
+
+    const NATIONS: &str = "https://test/subscribe/nation/update";
+    const CITIES: &str = "https://test/subscribe/city/update?nation_id=1";
+
+    #[test]
+    fn messages_are_tagged_and_interleaved() {
+        let (kit, _, socket) = subscription_kit();
+        block_on(async {
+            let mut set = kit.subscription_set::<&str>();
+            set.subscribe(
+                "nations",
+                SubscriptionModel::Nation,
+                SubscriptionEvent::Update,
+            )
+            .await
+            .unwrap();
+            set.subscribe_with_filter(
+                "cities",
+                SubscriptionEvent::Update,
+                SubscriptionFilter::new(SubscriptionModel::City).set_nation_id([1]),
+            )
+            .await
+            .unwrap();
+            assert_eq!(
+                set.tags().copied().collect::<Vec<_>>(),
+                vec!["nations", "cities"]
+            );
+            for id in 1..=3 {
+                socket
+                    .deliver(NATIONS, "NATION_UPDATE", json!({ "id": id }))
+                    .await
+                    .unwrap();
+            }
+            socket
+                .deliver(CITIES, "CITY_UPDATE", json!({ "id": 10 }))
+                .await
+                .unwrap();
+
+            let mut received = Vec::new();
+            for _ in 0..4 {
+                let (tag, message) = set.next().await.unwrap();
+                received.push((tag, message.data.get("id").unwrap().as_i64().unwrap()));
+            }
+            // the busy subscription doesn't hold up the other one
+            assert_eq!(
+                received,
+                vec![
+                    ("nations", 1),
+                    ("cities", 10),
+                    ("nations", 2),
+                    ("nations", 3)
+                ]
+            );
+        });
+    }
+
+    #[test]
+    fn ended_subscriptions_leave_the_set() {
+        let (kit, _, socket) = subscription_kit();
+        block_on(async {
+            let mut set = kit.subscription_set::<String>();
+            assert!(set.next().await.is_none());
+            set.subscribe(
+                "nations".into(),
+                SubscriptionModel::Nation,
+                SubscriptionEvent::Update,
+            )
+            .await
+            .unwrap();
+            socket
+                .deliver(NATIONS, "NATION_UPDATE", json!({ "id": 1 }))
+                .await
+                .unwrap();
+            let subscription = set.get(&"nations".to_string()).unwrap().clone();
+            kit.unsubscribe(&subscription).await.unwrap();
+            // what was queued is still delivered first
+            assert!(set.next().await.is_some());
+            assert!(set.next().await.is_none());
+            assert!(set.is_empty());
+        });
+    }
+
+    #[test]
+    fn tags_are_unique() {
+        let (kit, _, socket) = subscription_kit();
+        block_on(async {
+            let mut set = kit.subscription_set::<u8>();
+            let first = kit
+                .subscribe(SubscriptionModel::Nation, SubscriptionEvent::Update)
+                .await
+                .unwrap();
+            let second = kit
+                .subscribe_with_filter(
+                    SubscriptionEvent::Update,
+                    SubscriptionFilter::new(SubscriptionModel::City).set_nation_id([1]),
+                )
+                .await
+                .unwrap();
+            assert!(set.insert(1, first).is_none());
+            let replaced = set.insert(1, second).unwrap();
+            assert_eq!(replaced.channel().await, NATIONS);
+            assert_eq!(set.len(), 1);
+            assert_eq!(set.get(&1).unwrap().channel().await, CITIES);
+
+            let removed = set.remove(&1).unwrap();
+            assert!(set.remove(&1).is_none());
+            // removing doesn't unsubscribe while the subscription is kept
+            assert_eq!(socket.routed(), vec![CITIES, NATIONS]);
+            drop(removed);
+        });
+    }
+}
@@ -0,0 +1,513 @@
+// not every helper is used with every feature
+#![allow(dead_code)]
+
+use std::{
+    fmt::Debug,
+    sync::{Arc, Mutex},
+};
+
+#[cfg(feature = "subscriptions")]
+use std::{collections::HashMap, time::Instant};
+
+use async_trait::async_trait;
+use serde_json::json;
+
+#[cfg(feature = "subscriptions")]
+use tokio::sync::broadcast;
+
+#[cfg(feature = "subscriptions")]
+use crate::{
+    event::Event,
+    protocol::{ProtocolAction, PusherProtocol},
+    socket::{ConnectionEvent, ConnectionState, SendError, Socket, SocketStatus},
+    ReconnectPolicy, SubscriptionState,
+};
+use crate::{
+    request::{Client, Request, Response, ResponseResult},
+    Config, Headers, Kit, RateLimiter,
+};
+
+type Handler = dyn Fn(&Request) -> ResponseResult + Send + Sync;
+
+// answers requests with the handler and keeps them around to look at
+#[derive(Clone)]
+pub(crate) struct MockClient {
+    handler: Arc<Handler>,
+    requests: Arc<Mutex<Vec<Request>>>,
+}
+
+impl MockClient {
+    pub(crate) fn new(
+        handler: impl Fn(&Request) -> ResponseResult + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            handler: Arc::new(handler),
+            requests: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    pub(crate) fn requests(&self) -> Vec<Request> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    // the bodies of the GraphQL requests
+    pub(crate) fn bodies(&self) -> Vec<serde_json::Value> {
+        self.requests()
+            .iter()
+            .filter_map(|r| r.body.as_deref())
+            .filter_map(|b| serde_json::from_str(b).ok())
+            .collect()
+    }
+
+    fn handle(&self, request: &Request) -> ResponseResult {
+        self.requests.lock().unwrap().push(request.clone());
+        (self.handler)(request)
+    }
+}
+
+impl Debug for MockClient {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MockClient").finish()
+    }
+}
+
+#[async_trait]
+impl Client for MockClient {
+    #[cfg(any(feature = "async", feature = "subscriptions"))]
+    async fn request(&self, request: &Request) -> ResponseResult {
+        self.handle(request)
+    }
+
+    #[cfg(feature = "sync")]
+    fn request_sync(&self, request: &Request) -> ResponseResult {
+        self.handle(request)
+    }
+}
+
+pub(crate) fn ok(body: serde_json::Value) -> ResponseResult {
+    Ok(Response::new(200, body.to_string(), None))
+}
+
+// a response to a query with a single paginated field
+pub(crate) fn page(
+    data: Vec<serde_json::Value>,
+    page: i32,
+    has_more_pages: bool,
+) -> ResponseResult {
+    ok(json!({ "data": { "__paginate": paginated(data, page, has_more_pages) } }))
+}
+
+pub(crate) fn paginated(
+    data: Vec<serde_json::Value>,
+    page: i32,
+    has_more_pages: bool,
+) -> serde_json::Value {
+    json!({
+        "data": data,
+        "paginatorInfo": {
+            "count": data.len(),
+            "currentPage": page,
+            "firstItem": 0,
+            "hasMorePages": has_more_pages,
+            "lastItem": 0,
+            "lastPage": 0,
+            "perPage": data.len(),
+            "total": 0,
+        },
+    })
+}
+
+// the value of a variable in a GraphQL request body
+pub(crate) fn variable(body: &serde_json::Value, name: &str) -> serde_json::Value {
+    body["variables"][name].clone()
+}
+
+pub(crate) fn body(request: &Request) -> serde_json::Value {
+    serde_json::from_str(request.body.as_ref().unwrap()).unwrap()
+}
+
+// the page a single paginated field was requested at
+pub(crate) fn page_number(body: &serde_json::Value) -> i32 {
+    variable(body, "__page").as_i64().unwrap() as i32
+}
+
+// a page of two records with ids counting up from `first` on the first page,
+// `pages` of them
+pub(crate) fn counting_page(first: i64, page_number: i32, pages: i32) -> ResponseResult {
+    let id = first + (page_number as i64 - 1) * 2;
+    page(
+        vec![json!({ "id": id }), json!({ "id": id + 1 })],
+        page_number,
+        page_number < pages,
+    )
+}
+
+// the id of a record a paginator returned
+pub(crate) fn id(record: &crate::Value) -> i64 {
+    record
+        .as_object()
+        .and_then(|o| o.get("id").and_then(|id| id.value().as_i64()))
+        .unwrap()
+}
+
+pub(crate) fn now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+#[cfg(feature = "async")]
+fn sleep(
+    duration: std::time::Duration,
+) -> std::pin::Pin<Box<dyn std::future::Future<Output = ()> + Send + Sync>> {
+    Box::pin(tokio::time::sleep(duration))
+}
+
+// runs each future on a runtime of its own, like the one pnwkit-rs uses
+#[cfg(all(feature = "subscriptions", feature = "sync"))]
+fn spawn(
+    future: std::pin::Pin<Box<dyn std::future::Future<Output = ()> + Send>>,
+) -> Result<(), String> {
+    std::thread::spawn(move || runtime().block_on(future));
+    Ok(())
+}
+
+pub(crate) fn config(client: MockClient) -> Config {
+    Config {
+        api_key: "key".into(),
+        verified_bot_key: None,
+        verified_bot_key_api_key: None,
+        api_url: "https://test/graphql".into(),
+        #[cfg(feature = "subscriptions")]
+        socket_url: "wss://test/socket".into(),
+        #[cfg(feature = "subscriptions")]
+        subscribe_url: "https://test/subscribe/{model}/{event}".into(),
+        #[cfg(feature = "subscriptions")]
+        subscription_auth_url: "https://test/auth".into(),
+        rate_limiter: Arc::new(Mutex::new(RateLimiter::new(now))),
+        #[cfg(feature = "subscriptions")]
+        socket: Arc::new(MockSocket::new("1.1")),
+        #[cfg(feature = "subscriptions")]
+        socket_pool: None,
+        #[cfg(feature = "subscriptions")]
+        reconnect: ReconnectPolicy::new(),
+        #[cfg(feature = "subscriptions")]
+        subscribe_timeout: std::time::Duration::from_secs(5),
+        #[cfg(feature = "subscriptions")]
+        channel_lifetime: None,
+        #[cfg(all(feature = "subscriptions", feature = "async"))]
+        backfill: false,
+        #[cfg(feature = "subscriptions")]
+        queue_limit: None,
+        client: Box::new(client),
+        headers: Headers::new(),
+        now,
+        #[cfg(feature = "async")]
+        sleep,
+        #[cfg(feature = "sync")]
+        sleep_sync: std::thread::sleep,
+        #[cfg(all(feature = "subscriptions", feature = "sync"))]
+        spawn,
+        user_agent: "pnwkit-test".into(),
+    }
+    .update_headers()
+}
+
+pub(crate) fn kit(client: MockClient) -> Kit {
+    Kit::new(config(client))
+}
+
+#[cfg(any(feature = "async", feature = "subscriptions"))]
+pub(crate) fn runtime() -> tokio::runtime::Runtime {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_time()
+        .build()
+        .unwrap()
+}
+
+#[cfg(any(feature = "async", feature = "subscriptions"))]
+pub(crate) fn block_on<F: std::future::Future>(future: F) -> F::Output {
+    runtime().block_on(future)
+}
+
+// the clock only moves once every task is waiting on a timer, so timeouts and
+// timers fire in order without the test waiting for them
+#[cfg(any(feature = "async", feature = "subscriptions"))]
+pub(crate) fn block_on_paused<F: std::future::Future>(future: F) -> F::Output {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_time()
+        .start_paused(true)
+        .build()
+        .unwrap()
+        .block_on(future)
+}
+
+// answers the subscribe and auth endpoints, the channel is the url that was
+// requested so each set of filters gets a channel of its own
+#[cfg(feature = "subscriptions")]
+pub(crate) fn subscriptions(request: &Request) -> Option<ResponseResult> {
+    if request.url.starts_with("https://test/subscribe/") {
+        return Some(ok(json!({ "channel": request.url })));
+    }
+    if request.url == "https://test/auth" {
+        return Some(ok(json!({ "auth": "auth" })));
+    }
+    None
+}
+
+#[cfg(feature = "subscriptions")]
+pub(crate) fn subscription_kit() -> (Kit, MockClient, MockSocket) {
+    let client = MockClient::new(|request| {
+        subscriptions(request).unwrap_or_else(|| Err("unexpected request".into()))
+    });
+    let socket = MockSocket::new("1.1");
+    let kit = Kit::new(config(client.clone()).set_socket(Box::new(socket.clone())));
+    (kit, client, socket)
+}
+
+// a connection that is established as soon as it's connected and answers
+// subscribes itself, unless the channel is rejected
+#[cfg(feature = "subscriptions")]
+#[derive(Clone, Debug)]
+pub(crate) struct MockSocket(Arc<MockSocketInner>);
+
+#[cfg(feature = "subscriptions")]
+#[derive(Debug)]
+struct MockSocketInner {
+    socket_id: String,
+    connected: Event,
+    established: Event,
+    state: Mutex<ConnectionState>,
+    subscriptions: Mutex<HashMap<String, Arc<SubscriptionState>>>,
+    sent: Mutex<Vec<serde_json::Value>>,
+    rejected: Mutex<HashMap<String, String>>,
+    // channels whose subscribes are never answered
+    ignored: Mutex<Vec<String>>,
+    // connecting never gets the connection established
+    stalled: std::sync::atomic::AtomicBool,
+    events: broadcast::Sender<ConnectionEvent>,
+}
+
+#[cfg(feature = "subscriptions")]
+impl MockSocket {
+    pub(crate) fn new(socket_id: &str) -> Self {
+        Self(Arc::new(MockSocketInner {
+            socket_id: socket_id.into(),
+            connected: Event::new(),
+            established: Event::new(),
+            state: Mutex::new(ConnectionState::Disconnected),
+            subscriptions: Mutex::new(HashMap::new()),
+            sent: Mutex::new(Vec::new()),
+            rejected: Mutex::new(HashMap::new()),
+            ignored: Mutex::new(Vec::new()),
+            stalled: std::sync::atomic::AtomicBool::new(false),
+            events: broadcast::channel(16).0,
+        }))
+    }
+
+    pub(crate) fn reject(&self, channel: &str, reason: &str) {
+        self.0
+            .rejected
+            .lock()
+            .unwrap()
+            .insert(channel.into(), reason.into());
+    }
+
+    pub(crate) fn ignore(&self, channel: &str) {
+        self.0.ignored.lock().unwrap().push(channel.into());
+    }
+
+    pub(crate) fn stall(&self) {
+        self.0
+            .stalled
+            .store(true, std::sync::atomic::Ordering::Release);
+    }
+
+    // gives up on the connection like the socket does after a fatal close
+    pub(crate) fn fail(&self, reason: &str) {
+        self.set_state(ConnectionState::Failed(reason.into()));
+        self.emit(ConnectionEvent::Failed(reason.into()));
+    }
+
+    // the frames that were sent, parsed
+    pub(crate) fn sent(&self) -> Vec<serde_json::Value> {
+        self.0.sent.lock().unwrap().clone()
+    }
+
+    // the channels that were subscribed to, in order
+    pub(crate) fn subscribed(&self) -> Vec<String> {
+        self.sent()
+            .iter()
+            .filter(|f| f["event"] == "pusher:subscribe")
+            .map(|f| f["data"]["channel"].as_str().unwrap().to_string())
+            .collect()
+    }
+
+    pub(crate) fn routed(&self) -> Vec<String> {
+        let mut channels = self
+            .0
+            .subscriptions
+            .lock()
+            .unwrap()
+            .keys()
+            .cloned()
+            .collect::<Vec<_>>();
+        channels.sort();
+        channels
+    }
+
+    pub(crate) fn set_state(&self, state: ConnectionState) {
+        *self.0.state.lock().unwrap() = state;
+    }
+
+    // delivers a Pusher event like the read task would
+    pub(crate) async fn deliver(
+        &self,
+        channel: &str,
+        event: &str,
+        data: serde_json::Value,
+    ) -> Result<(), String> {
+        let frame = json!({ "event": event, "channel": channel, "data": data.to_string() });
+        let action = PusherProtocol::new().handle_text(&frame.to_string(), Instant::now())?;
+        let (event, bulk, data) = match action {
+            Some(ProtocolAction::Message {
+                event, bulk, data, ..
+            }) => (event, bulk, data),
+            action => return Err(format!("{} isn't a message: {:?}", event, action)),
+        };
+        let subscription = self.0.subscriptions.lock().unwrap().get(channel).cloned();
+        match subscription {
+            Some(subscription) => {
+                subscription
+                    .push_event(&event, channel.into(), now(), bulk, data)
+                    .await
+            },
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(feature = "subscriptions")]
+#[async_trait]
+impl Socket for MockSocket {
+    async fn init(&self, _kit: Kit) {}
+
+    async fn get_socket_id(&self) -> String {
+        self.0.socket_id.clone()
+    }
+
+    fn get_established(&'_ self) -> &'_ Event {
+        &self.0.established
+    }
+
+    fn get_connected(&'_ self) -> &'_ Event {
+        &self.0.connected
+    }
+
+    async fn get_state(&self) -> ConnectionState {
+        self.0.state.lock().unwrap().clone()
+    }
+
+    async fn status(&self) -> SocketStatus {
+        SocketStatus {
+            state: self.get_state().await,
+            socket_id: Some(self.0.socket_id.clone()),
+            ping_rtt: None,
+            since_last_message: None,
+            activity_timeout: std::time::Duration::from_secs(120),
+            reconnects: 0,
+            channel_messages: self
+                .routed()
+                .into_iter()
+                .map(|channel| (channel, 0))
+                .collect(),
+        }
+    }
+
+    fn connection_events(&self) -> broadcast::Receiver<ConnectionEvent> {
+        self.0.events.subscribe()
+    }
+
+    fn emit(&self, event: ConnectionEvent) {
+        let _ = self.0.events.send(event);
+    }
+
+    async fn add_subscription(&self, subscription: Arc<SubscriptionState>) {
+        let channel = subscription.channel.lock().await.clone();
+        self.add_subscription_channel(channel, subscription).await;
+    }
+
+    async fn remove_subscription(&self, subscription: Arc<SubscriptionState>) {
+        self.0
+            .subscriptions
+            .lock()
+            .unwrap()
+            .retain(|_, s| !Arc::ptr_eq(s, &subscription));
+    }
+
+    async fn get_subscription(&self, channel: String) -> Option<Arc<SubscriptionState>> {
+        self.0.subscriptions.lock().unwrap().get(&channel).cloned()
+    }
+
+    async fn add_subscription_channel(
+        &self,
+        channel: String,
+        subscription: Arc<SubscriptionState>,
+    ) {
+        self.0
+            .subscriptions
+            .lock()
+            .unwrap()
+            .insert(channel, subscription);
+    }
+
+    async fn remove_subscription_channel(&self, channel: String) {
+        self.0.subscriptions.lock().unwrap().remove(&channel);
+    }
+
+    async fn send(&self, data: String) -> Result<(), SendError> {
+        if let ConnectionState::Failed(reason) = self.get_state().await {
+            return Err(SendError::Abandoned(reason));
+        }
+        let frame = serde_json::from_str::<serde_json::Value>(&data).unwrap();
+        self.0.sent.lock().unwrap().push(frame.clone());
+        if frame["event"] != "pusher:subscribe" {
+            return Ok(());
+        }
+        let channel = frame["data"]["channel"].as_str().unwrap();
+        if self.0.ignored.lock().unwrap().iter().any(|c| c == channel) {
+            return Ok(());
+        }
+        let subscription = self.0.subscriptions.lock().unwrap().get(channel).cloned();
+        let rejected = self.0.rejected.lock().unwrap().get(channel).cloned();
+        if let Some(subscription) = subscription {
+            match rejected {
+                Some(reason) => subscription.set_error(channel, reason).await,
+                None => subscription.set_succeeded(channel).await,
+            }
+        }
+        Ok(())
+    }
+
+    async fn connect_ref(&self) -> Result<(), String> {
+        self.0.connected.set().await;
+        if self.0.stalled.load(std::sync::atomic::Ordering::Acquire) {
+            self.set_state(ConnectionState::Connecting);
+            return Ok(());
+        }
+        self.set_state(ConnectionState::Established);
+        self.0.established.set().await;
+        Ok(())
+    }
+
+    async fn connect(self) -> Result<(), String> {
+        self.connect_ref().await
+    }
+
+    async fn reconnect(&self) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn start_ping_pong_task(&self) {}
+}
@@ -1,9 +1,9 @@
 use dashmap::DashMap;
-use serde::{ser::SerializeMap, Serialize};
+use serde::{ser::SerializeMap, Deserialize, Serialize};
 
 use crate::{query::Query, Value};
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Deserialize, Serialize)]
 pub enum VariableType {
     Int,
     String,
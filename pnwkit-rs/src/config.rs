@@ -4,11 +4,13 @@ use std::sync::{Arc, Mutex};
 use std::{future::Future, pin::Pin};
 
 use pnwkit_core::{Headers, Kit, RateLimiter};
+#[cfg(feature = "subscriptions")]
+use pnwkit_core::{QueueLimit, ReconnectPolicy, SocketAssignment, SocketPool};
 
-use crate::client::Client;
+use crate::client::{Client, ClientOptions};
 
 #[cfg(feature = "subscriptions")]
-use crate::socket::Socket;
+use crate::socket::{Socket, SocketOptions};
 
 #[cfg(feature = "async")]
 fn sleep(duration: std::time::Duration) -> Pin<Box<dyn Future<Output = ()> + Send + Sync>> {
@@ -20,12 +22,45 @@ pub struct Config {
     pub verified_bot_key: Option<String>,
     pub verified_bot_key_api_key: Option<String>,
     pub api_url: String,
+    // for the http clients, the socket has its own below
+    pub proxy: Option<String>,
+    // DER encoded certificates the http clients trust on top of the webpki
+    // roots
+    pub root_certificates: Vec<Vec<u8>>,
+    pub timeout: Option<std::time::Duration>,
+    pub connect_timeout: Option<std::time::Duration>,
     #[cfg(feature = "subscriptions")]
     pub socket_url: String,
     #[cfg(feature = "subscriptions")]
     pub subscribe_url: String,
     #[cfg(feature = "subscriptions")]
     pub subscription_auth_url: String,
+    #[cfg(feature = "subscriptions")]
+    pub reconnect: ReconnectPolicy,
+    #[cfg(feature = "subscriptions")]
+    pub subscribe_timeout: std::time::Duration,
+    #[cfg(feature = "subscriptions")]
+    pub channel_lifetime: Option<std::time::Duration>,
+    // only create subscriptions are backfilled, see `pnwkit_core::Config`
+    #[cfg(all(feature = "subscriptions", feature = "async"))]
+    pub backfill: bool,
+    #[cfg(feature = "subscriptions")]
+    pub queue_limit: Option<QueueLimit>,
+    // `None` shares a single connection between every subscription
+    #[cfg(feature = "subscriptions")]
+    pub socket_assignment: Option<SocketAssignment>,
+    // an http proxy for the socket that supports CONNECT, see
+    // `set_socket_proxy`
+    #[cfg(feature = "subscriptions")]
+    pub socket_proxy: Option<String>,
+    // DER encoded certificates the socket trusts on top of the webpki roots
+    #[cfg(feature = "subscriptions")]
+    pub socket_root_certificates: Vec<Vec<u8>>,
+    #[cfg(feature = "subscriptions")]
+    pub socket_connect_timeout: Option<std::time::Duration>,
+    // sent with the socket's handshake
+    #[cfg(feature = "subscriptions")]
+    pub socket_headers: Vec<(String, String)>,
 }
 
 impl Config {
@@ -35,6 +70,10 @@ impl Config {
             verified_bot_key: None,
             verified_bot_key_api_key: None,
             api_url: "https://api.politicsandwar.com/graphql".into(),
+            proxy: None,
+            root_certificates: Vec::new(),
+            timeout: None,
+            connect_timeout: None,
             #[cfg(feature = "subscriptions")]
             socket_url: "wss://socket.politicsandwar.com/app/a22734a47847a64386c8?protocol=7"
                 .into(),
@@ -43,6 +82,26 @@ impl Config {
                 "https://api.politicsandwar.com/subscriptions/v1/subscribe/{model}/{event}".into(),
             #[cfg(feature = "subscriptions")]
             subscription_auth_url: "https://api.politicsandwar.com/subscriptions/v1/auth".into(),
+            #[cfg(feature = "subscriptions")]
+            reconnect: ReconnectPolicy::new(),
+            #[cfg(feature = "subscriptions")]
+            subscribe_timeout: std::time::Duration::from_secs(60),
+            #[cfg(feature = "subscriptions")]
+            channel_lifetime: None,
+            #[cfg(all(feature = "subscriptions", feature = "async"))]
+            backfill: false,
+            #[cfg(feature = "subscriptions")]
+            queue_limit: None,
+            #[cfg(feature = "subscriptions")]
+            socket_assignment: None,
+            #[cfg(feature = "subscriptions")]
+            socket_proxy: None,
+            #[cfg(feature = "subscriptions")]
+            socket_root_certificates: Vec::new(),
+            #[cfg(feature = "subscriptions")]
+            socket_connect_timeout: None,
+            #[cfg(feature = "subscriptions")]
+            socket_headers: Vec::new(),
         }
     }
 
@@ -66,6 +125,28 @@ impl Config {
         self
     }
 
+    // any proxy reqwest supports, http, https or socks, with credentials in
+    // the url
+    pub fn set_proxy(mut self, proxy: String) -> Self {
+        self.proxy = Some(proxy);
+        self
+    }
+
+    pub fn add_root_certificate(mut self, der: Vec<u8>) -> Self {
+        self.root_certificates.push(der);
+        self
+    }
+
+    pub fn set_timeout(mut self, timeout: std::time::Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn set_connect_timeout(mut self, connect_timeout: std::time::Duration) -> Self {
+        self.connect_timeout = Some(connect_timeout);
+        self
+    }
+
     #[cfg(feature = "subscriptions")]
     pub fn set_socket_url(mut self, socket_url: String) -> Self {
         self.socket_url = socket_url;
@@ -84,13 +165,99 @@ impl Config {
         self
     }
 
+    #[cfg(feature = "subscriptions")]
+    pub fn set_reconnect(mut self, reconnect: ReconnectPolicy) -> Self {
+        self.reconnect = reconnect;
+        self
+    }
+
+    #[cfg(feature = "subscriptions")]
+    pub fn set_subscribe_timeout(mut self, subscribe_timeout: std::time::Duration) -> Self {
+        self.subscribe_timeout = subscribe_timeout;
+        self
+    }
+
+    #[cfg(feature = "subscriptions")]
+    pub fn set_channel_lifetime(mut self, channel_lifetime: Option<std::time::Duration>) -> Self {
+        self.channel_lifetime = channel_lifetime;
+        self
+    }
+
+    #[cfg(all(feature = "subscriptions", feature = "async"))]
+    pub fn set_backfill(mut self, backfill: bool) -> Self {
+        self.backfill = backfill;
+        self
+    }
+
+    #[cfg(feature = "subscriptions")]
+    pub fn set_queue_limit(mut self, queue_limit: Option<QueueLimit>) -> Self {
+        self.queue_limit = queue_limit;
+        self
+    }
+
+    #[cfg(feature = "subscriptions")]
+    pub fn set_socket_assignment(mut self, socket_assignment: Option<SocketAssignment>) -> Self {
+        self.socket_assignment = socket_assignment;
+        self
+    }
+
+    // only `http://` proxies that support CONNECT are accepted, and ones with
+    // credentials in the url are rejected when the socket connects, tls to
+    // the socket runs through the tunnel
+    #[cfg(feature = "subscriptions")]
+    pub fn set_socket_proxy(mut self, socket_proxy: String) -> Self {
+        self.socket_proxy = Some(socket_proxy);
+        self
+    }
+
+    #[cfg(feature = "subscriptions")]
+    pub fn add_socket_root_certificate(mut self, der: Vec<u8>) -> Self {
+        self.socket_root_certificates.push(der);
+        self
+    }
+
+    #[cfg(feature = "subscriptions")]
+    pub fn set_socket_connect_timeout(
+        mut self,
+        socket_connect_timeout: std::time::Duration,
+    ) -> Self {
+        self.socket_connect_timeout = Some(socket_connect_timeout);
+        self
+    }
+
+    #[cfg(feature = "subscriptions")]
+    pub fn set_socket_header(mut self, name: String, value: String) -> Self {
+        self.socket_headers.push((name, value));
+        self
+    }
+
+    // panics if the http clients can't be built, like with a malformed proxy
+    // url or certificate, `try_to_kit` returns the error instead
     pub fn to_kit(self) -> Kit {
+        self.try_to_kit()
+            .unwrap_or_else(|e| panic!("failed to build the http client: {}", e))
+    }
+
+    pub fn try_to_kit(self) -> Result<Kit, String> {
+        let client = Client::new(&ClientOptions {
+            proxy: self.proxy,
+            root_certificates: self.root_certificates,
+            timeout: self.timeout,
+            connect_timeout: self.connect_timeout,
+        })?;
         let now = || {
             std::time::SystemTime::now()
                 .duration_since(std::time::UNIX_EPOCH)
                 .unwrap()
                 .as_secs()
         };
+        #[cfg(feature = "subscriptions")]
+        let socket_options = SocketOptions {
+            proxy: self.socket_proxy,
+            root_certificates: self.socket_root_certificates,
+            connect_timeout: self.socket_connect_timeout,
+            headers: self.socket_headers,
+        };
         let config = pnwkit_core::Config {
             api_key: self.api_key,
             verified_bot_key: self.verified_bot_key,
@@ -104,18 +271,41 @@ impl Config {
             subscription_auth_url: self.subscription_auth_url,
             rate_limiter: Arc::new(Mutex::new(RateLimiter::new(now))),
             #[cfg(feature = "subscriptions")]
-            socket: Box::new(Socket::new()),
-            client: Box::new(Client::new()),
+            socket: Arc::new(Socket::new(socket_options.clone())),
+            #[cfg(feature = "subscriptions")]
+            socket_pool: self.socket_assignment.map(|assignment| {
+                SocketPool::new(
+                    move || -> Box<dyn pnwkit_core::Socket> {
+                        Box::new(Socket::new(socket_options.clone()))
+                    },
+                    assignment,
+                )
+            }),
+            #[cfg(feature = "subscriptions")]
+            reconnect: self.reconnect,
+            #[cfg(feature = "subscriptions")]
+            subscribe_timeout: self.subscribe_timeout,
+            #[cfg(feature = "subscriptions")]
+            channel_lifetime: self.channel_lifetime,
+            #[cfg(all(feature = "subscriptions", feature = "async"))]
+            backfill: self.backfill,
+            #[cfg(feature = "subscriptions")]
+            queue_limit: self.queue_limit,
+            client: Box::new(client),
             headers: Headers::new(),
             now,
             #[cfg(feature = "async")]
             sleep,
             #[cfg(feature = "sync")]
             sleep_sync: std::thread::sleep,
+            // sync subscriptions still use the async websocket, on a hidden
+            // tokio runtime
+            #[cfg(all(feature = "subscriptions", feature = "sync"))]
+            spawn: crate::runtime::spawn,
             user_agent: format!("pnwkit-rs/{}", env!("CARGO_PKG_VERSION")),
         }
         .update_headers();
-        Kit::new(config)
+        Ok(Kit::new(config))
     }
 }
 
@@ -1,11 +1,30 @@
 mod client;
 mod config;
+#[cfg(all(feature = "subscriptions", feature = "sync"))]
+mod runtime;
 #[cfg(feature = "subscriptions")]
 mod socket;
+#[cfg(all(test, any(feature = "async", feature = "subscriptions")))]
+mod test_util;
 
 pub use config::Config;
+#[cfg(all(feature = "subscriptions", feature = "sync"))]
+pub use pnwkit_core::BlockingIter;
+#[cfg(any(feature = "async", feature = "sync"))]
+pub use pnwkit_core::MultiPaginator;
+#[cfg(feature = "async")]
+pub use pnwkit_core::ShardedPaginator;
 pub use pnwkit_core::{
-    field, field_as, Data, Field, FieldType, Kit, Object, Paginator, Value, Variable, VariableType,
+    field, field_as, Data, Field, FieldType, Kit, Object, Paginator, PaginatorCheckpoint,
+    PaginatorInfo, PaginatorItemError, Value, Variable, VariableType,
 };
 #[cfg(feature = "subscriptions")]
-pub use pnwkit_core::{Subscription, SubscriptionEvent, SubscriptionModel};
+pub use pnwkit_core::{
+    AccountPayload, AlliancePayload, AlliancePositionPayload, BBGamePayload, BBTeamPayload,
+    BankrecPayload, BountyPayload, CityPayload, ConnectionEvent, ConnectionState, EmbargoPayload,
+    NationPayload, OverflowPolicy, QueueLimit, ReconnectPolicy, SendError, SocketAssignment,
+    SocketStatus, Subscription, SubscriptionEvent, SubscriptionFilter, SubscriptionJournal,
+    SubscriptionMessage, SubscriptionModel, SubscriptionPayload, SubscriptionReceiver,
+    SubscriptionSet, TaxBracketPayload, TradePayload, TradepricePayload, TreasureTradePayload,
+    TreatyPayload, TypedSubscription, WarAttackPayload, WarPayload,
+};
@@ -0,0 +1,30 @@
+use std::{future::Future, pin::Pin, sync::OnceLock};
+
+use tokio::runtime::{Builder, Handle};
+
+// subscriptions made from sync code run here, a tokio runtime on a thread of
+// its own so the caller doesn't need one, it's started by the first of them
+static RUNTIME: OnceLock<Result<Handle, String>> = OnceLock::new();
+
+fn handle() -> Result<&'static Handle, String> {
+    RUNTIME
+        .get_or_init(|| {
+            let runtime = Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .map_err(|e| format!("failed to build the subscription runtime: {}", e))?;
+            let handle = runtime.handle().clone();
+            std::thread::Builder::new()
+                .name("pnwkit-subscriptions".into())
+                .spawn(move || runtime.block_on(std::future::pending::<()>()))
+                .map_err(|e| format!("failed to start the subscription runtime: {}", e))?;
+            Ok(handle)
+        })
+        .as_ref()
+        .map_err(Clone::clone)
+}
+
+pub(crate) fn spawn(future: Pin<Box<dyn Future<Output = ()> + Send>>) -> Result<(), String> {
+    handle()?.spawn(future);
+    Ok(())
+}
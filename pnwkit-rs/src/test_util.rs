@@ -0,0 +1,33 @@
+use std::sync::Arc;
+
+use tokio_rustls::{
+    rustls::{Certificate, PrivateKey, ServerConfig},
+    TlsAcceptor,
+};
+
+// a test ca and a certificate it signed for localhost and 127.0.0.1, both
+// good for a hundred years
+pub(crate) const CA: &[u8] = include_bytes!("../testdata/ca.der");
+const SERVER_CERTIFICATE: &[u8] = include_bytes!("../testdata/server.der");
+const SERVER_KEY: &[u8] = include_bytes!("../testdata/server.key.der");
+
+// kits are made outside of the runtime, the blocking http client can't be
+// dropped inside of one
+pub(crate) fn runtime() -> tokio::runtime::Runtime {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+}
+
+pub(crate) fn tls_acceptor() -> TlsAcceptor {
+    let config = ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(
+            vec![Certificate(SERVER_CERTIFICATE.to_vec())],
+            PrivateKey(SERVER_KEY.to_vec()),
+        )
+        .unwrap();
+    TlsAcceptor::from(Arc::new(config))
+}